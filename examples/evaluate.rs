@@ -0,0 +1,25 @@
+//! Runs wallet detection over a directory of labeled fixtures and prints the evaluation.
+//!
+//! Usage: cargo run --example evaluate [fixtures directory]
+
+use std::{env, path::PathBuf, process::ExitCode};
+
+use wallet_fingerprint::evaluation::evaluate_fixtures;
+
+fn main() -> ExitCode {
+    let dir = env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/wallets"));
+
+    match evaluate_fixtures(&dir) {
+        Ok(evaluation) => {
+            print!("{}", evaluation);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,107 @@
+"""Records the outputs of the reference implementation in the wallet fixtures.
+
+Usage: python3 fixtures/generate_reference.py path/to/wallet-fingerprinting
+
+Each fixture's transaction is fetched from the mempool.space API, which is the input format
+fingerprinting.py works on, and the `reference` section of the fixture is overwritten.
+Values are normalized to the vocabulary of src/conformance.rs.
+"""
+
+import glob
+import hashlib
+import json
+import os
+import sys
+import urllib.request
+
+API = "https://mempool.space/api"
+FIXTURES = os.path.join(os.path.dirname(os.path.abspath(__file__)), "wallets")
+
+
+def read_varint(data, pos):
+    n = data[pos]
+    if n < 0xFD:
+        return n, pos + 1
+    size = {0xFD: 2, 0xFE: 4, 0xFF: 8}[n]
+    return int.from_bytes(data[pos + 1 : pos + 1 + size], "little"), pos + 1 + size
+
+
+def txid(tx_hex):
+    """Hash of the transaction without its witnesses"""
+    data = bytes.fromhex(tx_hex)
+    if data[4:6] != b"\x00\x01":
+        stripped = data
+    else:
+        pos = 6
+        n_in, pos = read_varint(data, pos)
+        for _ in range(n_in):
+            pos += 36
+            script_len, pos = read_varint(data, pos)
+            pos += script_len + 4
+        n_out, pos = read_varint(data, pos)
+        for _ in range(n_out):
+            pos += 8
+            script_len, pos = read_varint(data, pos)
+            pos += script_len
+        io_end = pos
+        for _ in range(n_in):
+            n_items, pos = read_varint(data, pos)
+            for _ in range(n_items):
+                item_len, pos = read_varint(data, pos)
+                pos += item_len
+        stripped = data[:4] + data[6:io_end] + data[pos:]
+    return hashlib.sha256(hashlib.sha256(stripped).digest()).digest()[::-1].hex()
+
+
+def fetch(txid):
+    with urllib.request.urlopen(f"{API}/tx/{txid}") as response:
+        return json.load(response)
+
+
+def name(value):
+    """Lowercase name of an enum member or string, without separators"""
+    value = getattr(value, "name", value)
+    return str(value).lower().replace("_", "").replace(" ", "")
+
+
+def script_type(value):
+    # mempool.space prefixes segwit types with their version, e.g. v0_p2wpkh
+    value = name(value)
+    return value[2:] if value.startswith(("v0", "v1")) else value
+
+
+def reference(fp, tx):
+    wallets, _ = fp.detect_wallet(tx)
+    return {
+        "wallets": ",".join(sorted(name(wallet) for wallet in wallets)),
+        "version": str(tx["version"]),
+        "anti_fee_sniping": json.dumps(bool(fp.is_anti_fee_sniping(tx))),
+        "uncompressed_pubkeys": json.dumps(bool(fp.using_uncompressed_pubkeys(tx))),
+        "signals_rbf": json.dumps(bool(fp.signals_rbf(tx))),
+        "low_r": json.dumps(bool(fp.low_r_only(tx))),
+        "address_reuse": json.dumps(bool(fp.address_reuse(tx))),
+        "input_types": ",".join(script_type(t) for t in fp.get_spending_types(tx)),
+        "output_types": ",".join(script_type(t) for t in fp.get_sending_types(tx)),
+        "input_order": ",".join(sorted(name(o) for o in fp.get_input_order(tx))),
+        "output_structure": ",".join(sorted(name(s) for s in fp.get_output_structure(tx))),
+        "change_index": str(fp.get_change_index(tx)),
+    }
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+    sys.path.insert(0, sys.argv[1])
+    import fingerprinting
+
+    for path in sorted(glob.glob(os.path.join(FIXTURES, "*.json"))):
+        with open(path) as f:
+            fixture = json.load(f)
+        fixture["reference"] = reference(fingerprinting, fetch(txid(fixture["tx"])))
+        with open(path, "w") as f:
+            f.write(json.dumps(fixture, indent=2) + "\n")
+        print(path)
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,19 @@
+#![no_main]
+
+use bitcoin::{consensus::Decodable, Transaction};
+use libfuzzer_sys::fuzz_target;
+use wallet_fingerprint::heuristics::Heuristics;
+
+// Input is a consensus encoded transaction followed by a consensus encoded list of the previous
+// transactions, which need not contain the outputs the inputs spend
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    let Ok(tx) = Transaction::consensus_decode(&mut reader) else {
+        return;
+    };
+    let prev_txs = Vec::<Transaction>::consensus_decode(&mut reader).unwrap_or_default();
+
+    if let Ok(heuristics) = Heuristics::new(tx, prev_txs) {
+        let _ = heuristics.fingerprint();
+    }
+});
@@ -0,0 +1,187 @@
+//! Analysing many transactions at once, such as every transaction of a block.
+//! With the `rayon` feature transactions are analysed in parallel.
+
+use bitcoin::{Block, Transaction};
+
+use crate::{
+    change::ChangeHints,
+    detect_wallet_from_prevouts,
+    heuristics::Heuristics,
+    prevouts::{lookup_prevouts, Overlay, PrevoutLookup},
+    WalletType,
+};
+
+/// The heuristics of a transaction and the wallets it may have been created by
+#[derive(Debug)]
+pub struct Analysis {
+    pub heuristics: Heuristics,
+    /// Wallets detection could not rule out, sorted
+    pub wallets: Vec<WalletType>,
+    /// Why the other wallets were ruled out
+    pub reasoning: Vec<String>,
+}
+
+fn analyze<L: PrevoutLookup + ?Sized>(tx: &Transaction, lookup: &L) -> Result<Analysis, String> {
+    let txid = tx.compute_txid();
+    if tx.is_coinbase() {
+        return Err(format!("{}: coinbase transactions spend no outputs", txid));
+    }
+    let prev_outs = lookup_prevouts(tx, lookup)
+        .map_err(|e| format!("{}: {}", txid, e))?
+        .ok_or_else(|| format!("{}: unknown previous output", txid))?;
+
+    let heuristics = Heuristics::from_prevouts(tx, &prev_outs, &ChangeHints::default());
+    let (wallets, reasoning) = detect_wallet_from_prevouts(tx, &prev_outs);
+    let mut wallets = wallets.into_iter().collect::<Vec<_>>();
+    wallets.sort();
+    Ok(Analysis {
+        heuristics,
+        wallets,
+        reasoning,
+    })
+}
+
+/// Maps every item, in parallel with the `rayon` feature, keeping the order of the items
+fn map_ordered<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync + Send) -> Vec<R> {
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        items.par_iter().map(f).collect()
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        items.iter().map(f).collect()
+    }
+}
+
+/// Analyses every transaction, resolving previous outputs from `lookup`.
+/// Results are in the order of the transactions; a transaction that cannot be analysed,
+/// e.g. because a previous output is unknown, has an error instead.
+pub fn analyze_transactions<'a, L: PrevoutLookup + Sync + ?Sized>(
+    txs: impl IntoIterator<Item = &'a Transaction>,
+    lookup: &L,
+) -> Vec<Result<Analysis, String>> {
+    let txs = txs.into_iter().collect::<Vec<_>>();
+    map_ordered(&txs, |tx| analyze(tx, lookup))
+}
+
+/// Analyses every non-coinbase transaction of a block, in block order.
+/// Outputs created in the block are resolved from the block itself, without a confirmation height,
+/// other previous outputs from `lookup`.
+pub fn analyze_block<L: PrevoutLookup + Sync + ?Sized>(
+    block: &Block,
+    lookup: &L,
+) -> Vec<Result<Analysis, String>> {
+    let mut overlay = Overlay::new(lookup);
+    for tx in block.txdata.iter() {
+        overlay.add_outputs(tx, None);
+    }
+    analyze_transactions(block.txdata.iter().filter(|tx| !tx.is_coinbase()), &overlay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        detect_wallet,
+        prevouts::Prevout,
+        test_utils::{ScriptType, TxBuilder},
+    };
+    use bitcoin::{
+        absolute::LockTime, block::Header, block::Version as BlockVersion, hashes::Hash,
+        transaction::Version, Amount, BlockHash, CompactTarget, OutPoint, ScriptBuf, Sequence,
+        TxIn, TxMerkleNode, TxOut, Witness,
+    };
+    use std::collections::{HashMap, HashSet};
+
+    fn spend(seed: u64) -> (Transaction, Vec<Transaction>) {
+        TxBuilder::new(seed)
+            .input(Amount::from_sat(100_000), ScriptType::P2wpkh)
+            .output(Amount::from_sat(40_000), ScriptType::P2pkh)
+            .change(Amount::from_sat(59_000), ScriptType::P2wpkh)
+            .build()
+    }
+
+    /// Every output of the transactions, confirmed at height 1
+    fn lookup(txs: &[Transaction]) -> HashMap<OutPoint, Prevout> {
+        txs.iter()
+            .flat_map(|tx| {
+                let txid = tx.compute_txid();
+                tx.output.iter().enumerate().map(move |(vout, txout)| {
+                    let prevout = Prevout {
+                        txout: txout.clone(),
+                        height: Some(1),
+                    };
+                    (OutPoint::new(txid, vout as u32), prevout)
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_analyze_transactions() {
+        let (tx1, prev_txs1) = spend(1);
+        let (tx2, _) = spend(2);
+        let (tx3, prev_txs3) = spend(3);
+        let lookup = lookup(&[prev_txs1.clone(), prev_txs3.clone()].concat());
+
+        let results = analyze_transactions([&tx1, &tx2, &tx3], &lookup);
+        assert_eq!(results.len(), 3);
+        assert!(results[1]
+            .as_ref()
+            .unwrap_err()
+            .contains("unknown previous output"));
+        for (result, (tx, prev_txs)) in [
+            (&results[0], (&tx1, &prev_txs1)),
+            (&results[2], (&tx3, &prev_txs3)),
+        ] {
+            let analysis = result.as_ref().unwrap();
+            assert_eq!(analysis.heuristics.txid, tx.compute_txid());
+            let (wallets, reasoning) = detect_wallet(tx, prev_txs).unwrap();
+            assert_eq!(
+                analysis.wallets.iter().copied().collect::<HashSet<_>>(),
+                wallets
+            );
+            assert_eq!(analysis.reasoning, reasoning);
+        }
+    }
+
+    #[test]
+    fn test_analyze_block() {
+        let (tx1, prev_txs1) = spend(1);
+        // Spends the change of the first transaction, which is created in the same block
+        let tx2 = Transaction {
+            input: vec![TxIn {
+                previous_output: OutPoint::new(tx1.compute_txid(), 1),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            ..tx1.clone()
+        };
+        let coinbase = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut::NULL],
+        };
+        let block = Block {
+            header: Header {
+                version: BlockVersion::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata: vec![coinbase, tx1.clone(), tx2.clone()],
+        };
+
+        let results = analyze_block(&block, &lookup(&prev_txs1));
+        let txids = results
+            .into_iter()
+            .map(|result| result.unwrap().heuristics.txid)
+            .collect::<Vec<_>>();
+        assert_eq!(txids, vec![tx1.compute_txid(), tx2.compute_txid()]);
+    }
+}
@@ -0,0 +1,536 @@
+//! Reading blocks straight from the `blocks` directory of a Bitcoin Core data directory,
+//! so the whole chain can be fingerprinted without RPC access.
+//!
+//! Block files (`blk*.dat`) are a sequence of records, each a network magic, a little endian
+//! block size and the block itself. Blocks are stored in the order they were downloaded, which is
+//! not height order and includes stale blocks, so the best chain is rebuilt from the headers.
+//! Since Bitcoin Core 28 the files are obfuscated by XORing them with the 8 byte key in `xor.dat`.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use bitcoin::{
+    block::Header, consensus::deserialize, hashes::Hash, pow::Work, Block, BlockHash, Network, Txid,
+};
+
+use crate::{
+    change::ChangeHints,
+    heuristics::Heuristics,
+    prevouts::{lookup_prevouts, Overlay, PrevoutIndex},
+};
+
+const XOR_KEY_LEN: usize = 8;
+
+/// Reader over a block file that undoes the XOR obfuscation
+struct BlockFileReader {
+    inner: BufReader<File>,
+    xor_key: [u8; XOR_KEY_LEN],
+    position: u64,
+}
+
+impl BlockFileReader {
+    fn open(path: &Path, xor_key: [u8; XOR_KEY_LEN]) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Self {
+            inner: BufReader::new(file),
+            xor_key,
+            position: 0,
+        })
+    }
+
+    fn seek(&mut self, position: u64) -> std::io::Result<()> {
+        self.inner.seek(SeekFrom::Start(position))?;
+        self.position = position;
+        Ok(())
+    }
+}
+
+impl Read for BlockFileReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        for (i, byte) in buf[..read].iter_mut().enumerate() {
+            *byte ^= self.xor_key[(self.position as usize + i) % XOR_KEY_LEN];
+        }
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+/// Where a block is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BlockLocation {
+    /// Index into the sorted block files
+    file: usize,
+    /// Offset of the serialized block, after its magic and size
+    offset: u64,
+}
+
+/// The block files of a Bitcoin Core data directory
+#[derive(Debug, Clone)]
+pub struct BlockFiles {
+    paths: Vec<PathBuf>,
+    xor_key: [u8; XOR_KEY_LEN],
+    magic: [u8; 4],
+}
+
+impl BlockFiles {
+    /// Opens the `blocks` directory of a data directory, e.g. `~/.bitcoin/blocks`
+    pub fn open(blocks_dir: &Path, network: Network) -> Result<Self, String> {
+        let with_dir = |e: std::io::Error| format!("{}: {}", blocks_dir.display(), e);
+
+        let mut paths = fs::read_dir(blocks_dir)
+            .map_err(with_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(with_dir)?;
+        paths.retain(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("blk") && name.ends_with(".dat"))
+        });
+        paths.sort();
+
+        // Files written before Bitcoin Core 28 are not obfuscated
+        let xor_key = match fs::read(blocks_dir.join("xor.dat")) {
+            Ok(key) => key
+                .try_into()
+                .map_err(|_| format!("{}: invalid xor.dat", blocks_dir.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => [0; XOR_KEY_LEN],
+            Err(e) => return Err(with_dir(e)),
+        };
+
+        Ok(Self {
+            paths,
+            xor_key,
+            magic: network.magic().to_bytes(),
+        })
+    }
+
+    /// Reads the header and location of every block in the files
+    fn index(&self) -> Result<Vec<(Header, BlockLocation)>, String> {
+        let mut blocks = Vec::new();
+        for (file, path) in self.paths.iter().enumerate() {
+            let with_path = |e: std::io::Error| format!("{}: {}", path.display(), e);
+            let len = fs::metadata(path).map_err(with_path)?.len();
+            let mut reader = BlockFileReader::open(path, self.xor_key)?;
+
+            let mut position = 0;
+            // Files are preallocated, a record starting with zeros marks the end of the data
+            while position + 8 <= len {
+                let mut record = [0u8; 8];
+                reader.read_exact(&mut record).map_err(with_path)?;
+                if record[..4] != self.magic {
+                    break;
+                }
+                let size = u32::from_le_bytes(record[4..].try_into().expect("4 bytes"));
+                let offset = position + 8;
+                // The node stopped while writing the last block
+                if offset + (size as u64).max(Header::SIZE as u64) > len {
+                    break;
+                }
+                let mut header = [0u8; Header::SIZE];
+                reader.read_exact(&mut header).map_err(with_path)?;
+                let header = deserialize::<Header>(&header)
+                    .map_err(|e| format!("{}: block at {}: {}", path.display(), offset, e))?;
+                blocks.push((header, BlockLocation { file, offset }));
+
+                position = offset + size as u64;
+                reader.seek(position).map_err(with_path)?;
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Hashes and locations of the blocks of the chain with the most work, in height order
+    fn best_chain(&self) -> Result<Vec<(BlockHash, BlockLocation)>, String> {
+        let index = self.index()?;
+        let mut children: HashMap<BlockHash, Vec<usize>> = HashMap::new();
+        for (i, (header, _)) in index.iter().enumerate() {
+            children.entry(header.prev_blockhash).or_default().push(i);
+        }
+
+        // Accumulate work from the genesis block, skipping blocks whose parent is missing
+        let mut parent = vec![None; index.len()];
+        let mut best: Option<(Work, usize)> = None;
+        let mut stack = children
+            .get(&BlockHash::all_zeros())
+            .into_iter()
+            .flatten()
+            .map(|&i| (i, index[i].0.work()))
+            .collect::<Vec<_>>();
+        while let Some((i, work)) = stack.pop() {
+            if best.is_none_or(|(best_work, _)| work > best_work) {
+                best = Some((work, i));
+            }
+            for &child in children.get(&index[i].0.block_hash()).into_iter().flatten() {
+                parent[child] = Some(i);
+                stack.push((child, work + index[child].0.work()));
+            }
+        }
+
+        let mut chain = Vec::new();
+        let mut tip = best.map(|(_, i)| i);
+        while let Some(i) = tip {
+            chain.push((index[i].0.block_hash(), index[i].1));
+            tip = parent[i];
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// Iterates over the blocks of the best chain in height order, starting at the genesis block
+    pub fn blocks(&self) -> Result<impl Iterator<Item = Result<Block, String>> + '_, String> {
+        self.blocks_from(0)
+    }
+
+    /// Like [`BlockFiles::blocks`], starting at `height`
+    pub fn blocks_from(
+        &self,
+        height: u32,
+    ) -> Result<impl Iterator<Item = Result<Block, String>> + '_, String> {
+        Ok(self.read_blocks(self.best_chain()?, height))
+    }
+
+    /// Like [`BlockFiles::blocks_from`], resuming after `tip`, the last block already read.
+    /// Fails if `tip` is no longer in the best chain, e.g. after a reorg.
+    fn blocks_after(
+        &self,
+        tip: Option<(u32, BlockHash)>,
+    ) -> Result<impl Iterator<Item = Result<Block, String>> + '_, String> {
+        let chain = self.best_chain()?;
+        let start = match tip {
+            None => 0,
+            Some((height, hash)) => {
+                if chain.get(height as usize).map(|(hash, _)| *hash) != Some(hash) {
+                    return Err(format!(
+                        "block {} at height {} is no longer in the best chain",
+                        hash, height
+                    ));
+                }
+                height + 1
+            }
+        };
+        Ok(self.read_blocks(chain, start))
+    }
+
+    /// Reads the blocks of `chain`, starting at `height`
+    fn read_blocks(
+        &self,
+        chain: Vec<(BlockHash, BlockLocation)>,
+        height: u32,
+    ) -> impl Iterator<Item = Result<Block, String>> + '_ {
+        let mut reader: Option<(usize, BlockFileReader)> = None;
+
+        chain
+            .into_iter()
+            .skip(height as usize)
+            .map(move |(_, location)| {
+                let path = &self.paths[location.file];
+                let with_path = |e: std::io::Error| format!("{}: {}", path.display(), e);
+                if reader
+                    .as_ref()
+                    .is_none_or(|(file, _)| *file != location.file)
+                {
+                    reader = Some((location.file, BlockFileReader::open(path, self.xor_key)?));
+                }
+                let (_, reader) = reader.as_mut().expect("Opened above");
+
+                reader.seek(location.offset - 4).map_err(with_path)?;
+                let mut size = [0u8; 4];
+                reader.read_exact(&mut size).map_err(with_path)?;
+                let mut block = vec![0u8; u32::from_le_bytes(size) as usize];
+                reader.read_exact(&mut block).map_err(with_path)?;
+                deserialize(&block)
+                    .map_err(|e| format!("{}: block at {}: {}", path.display(), location.offset, e))
+            })
+    }
+}
+
+/// The heuristics of every non-coinbase transaction of a block
+#[derive(Debug)]
+pub struct BlockHeuristics {
+    pub height: u32,
+    pub block_hash: BlockHash,
+    pub transactions: Vec<Heuristics>,
+    /// Transactions spending outputs that were not seen while scanning
+    pub unresolved: Vec<Txid>,
+}
+
+/// Runs the heuristics over every non-coinbase transaction of the best chain, in height order.
+/// Previous outputs are resolved from `index`, which is updated with every scanned block.
+/// Scanning starts after the last block connected to the index, so every input is resolved as
+/// long as the index was built from the genesis block.
+/// Fails if that block was reorganized out of the best chain, since the index cannot disconnect
+/// blocks and would otherwise keep the outputs of the stale blocks; rebuild the index then.
+pub fn scan_blocks<'a, I: PrevoutIndex>(
+    files: &'a BlockFiles,
+    index: &'a mut I,
+) -> Result<impl Iterator<Item = Result<BlockHeuristics, String>> + 'a, String> {
+    let tip = index.tip()?;
+    let start = tip.map_or(0, |(height, _)| height + 1);
+
+    Ok(files
+        .blocks_after(tip)?
+        .zip(start..)
+        .map(move |(block, height)| {
+            let block = block?;
+            // Outputs created earlier in the block are not in the index yet
+            let mut overlay = Overlay::new(&*index);
+            let mut transactions = Vec::new();
+            let mut unresolved = Vec::new();
+
+            for tx in block.txdata.iter() {
+                let txid = tx.compute_txid();
+                if !tx.is_coinbase() {
+                    match lookup_prevouts(tx, &overlay)? {
+                        Some(prev_outs) => transactions.push(Heuristics::from_prevouts(
+                            tx,
+                            &prev_outs,
+                            &ChangeHints::default(),
+                        )),
+                        None => unresolved.push(txid),
+                    }
+                }
+                overlay.add_outputs(tx, Some(height));
+            }
+
+            index.connect_block(&block, height)?;
+            Ok(BlockHeuristics {
+                height,
+                block_hash: block.block_hash(),
+                transactions,
+                unresolved,
+            })
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        prevouts::{Prevout, PrevoutLookup},
+        test_utils::{ScriptType, TxBuilder},
+    };
+    use bitcoin::{
+        absolute::LockTime, block::Version as BlockVersion, consensus::serialize,
+        transaction::Version, Amount, CompactTarget, OutPoint, ScriptBuf, Sequence, Transaction,
+        TxIn, TxMerkleNode, TxOut, Witness,
+    };
+
+    fn coinbase(height: u8) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(vec![0x01, height]),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(50_0000_0000),
+                script_pubkey: ScriptBuf::new_op_return([height]),
+            }],
+        }
+    }
+
+    fn block(prev_blockhash: BlockHash, height: u8, mut txdata: Vec<Transaction>) -> Block {
+        txdata.insert(0, coinbase(height));
+        let mut block = Block {
+            header: Header {
+                version: BlockVersion::TWO,
+                prev_blockhash,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 1_700_000_000 + height as u32,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().expect("Has transactions");
+        block
+    }
+
+    fn write_block_file(path: &Path, blocks: &[&Block], xor_key: [u8; XOR_KEY_LEN]) {
+        let mut data = Vec::new();
+        for block in blocks {
+            let block = serialize(*block);
+            data.extend(Network::Regtest.magic().to_bytes());
+            data.extend((block.len() as u32).to_le_bytes());
+            data.extend(block);
+        }
+        // Preallocated space
+        data.extend([0u8; 64]);
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte ^= xor_key[i % XOR_KEY_LEN];
+        }
+        fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn test_scan_blocks() {
+        let (tx, prev_txs) = TxBuilder::new(0)
+            .input(Amount::from_sat(100_000), ScriptType::P2wpkh)
+            .output(Amount::from_sat(60_000), ScriptType::P2tr)
+            .change(Amount::from_sat(39_000), ScriptType::P2wpkh)
+            .build();
+
+        // The funding transaction spends an output that is not in the chain
+        let genesis = block(BlockHash::all_zeros(), 0, prev_txs.clone());
+        let block1 = block(genesis.block_hash(), 1, vec![tx.clone()]);
+        let block2 = block(block1.block_hash(), 2, vec![]);
+        let stale = block(genesis.block_hash(), 3, vec![]);
+
+        let dir = std::env::temp_dir().join(format!("wallet-blocks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let xor_key = [0x5a, 0x01, 0xff, 0x00, 0x33, 0x80, 0x7e, 0x11];
+        fs::write(dir.join("xor.dat"), xor_key).unwrap();
+        write_block_file(&dir.join("blk00000.dat"), &[&block2, &stale], xor_key);
+        write_block_file(&dir.join("blk00001.dat"), &[&block1, &genesis], xor_key);
+
+        let files = BlockFiles::open(&dir, Network::Regtest).unwrap();
+        let blocks = files
+            .blocks()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            blocks,
+            vec![genesis.clone(), block1.clone(), block2.clone()]
+        );
+
+        let mut index = HashMap::new();
+        let scanned = scan_blocks(&files, &mut index)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(scanned.len(), 3);
+        assert_eq!(scanned[0].block_hash, genesis.block_hash());
+        assert_eq!(scanned[0].unresolved, vec![prev_txs[0].compute_txid()]);
+        assert_eq!(scanned[1].height, 1);
+        assert_eq!(scanned[1].transactions.len(), 1);
+        assert_eq!(scanned[1].transactions[0].txid, tx.compute_txid());
+        assert!(scanned[1].unresolved.is_empty());
+        assert!(scanned[2].transactions.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// An index that remembers its tip, like a store on disk
+    #[derive(Default)]
+    struct TipIndex {
+        prevouts: HashMap<OutPoint, Prevout>,
+        tip: Option<(u32, BlockHash)>,
+    }
+
+    impl PrevoutLookup for TipIndex {
+        fn prevout(&self, outpoint: &OutPoint) -> Result<Option<Prevout>, String> {
+            self.prevouts.prevout(outpoint)
+        }
+    }
+
+    impl PrevoutIndex for TipIndex {
+        fn tip(&self) -> Result<Option<(u32, BlockHash)>, String> {
+            Ok(self.tip)
+        }
+
+        fn connect_block(&mut self, block: &Block, height: u32) -> Result<(), String> {
+            self.prevouts.connect_block(block, height)?;
+            self.tip = Some((height, block.block_hash()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_resume_scan() {
+        let (tx, prev_txs) = TxBuilder::new(1)
+            .input(Amount::from_sat(100_000), ScriptType::P2wpkh)
+            .output(Amount::from_sat(60_000), ScriptType::P2tr)
+            .build();
+        let genesis = block(BlockHash::all_zeros(), 0, prev_txs);
+        let block1 = block(genesis.block_hash(), 1, vec![tx]);
+        let block2 = block(block1.block_hash(), 2, vec![]);
+        // A competing chain with more work, which does not have the transaction
+        let fork1 = block(genesis.block_hash(), 1, vec![]);
+        let fork2 = block(fork1.block_hash(), 2, vec![]);
+        let fork3 = block(fork2.block_hash(), 3, vec![]);
+
+        let dir = std::env::temp_dir().join(format!("wallet-blocks-reorg-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let scan = |index: &mut TipIndex| {
+            let files = BlockFiles::open(&dir, Network::Regtest).unwrap();
+            let hashes = scan_blocks(&files, index)?
+                .map(|block| block.map(|block| block.block_hash))
+                .collect::<Result<Vec<_>, _>>();
+            hashes
+        };
+
+        let mut index = TipIndex::default();
+        write_block_file(
+            &dir.join("blk00000.dat"),
+            &[&genesis, &block1],
+            [0; XOR_KEY_LEN],
+        );
+        assert_eq!(
+            scan(&mut index).unwrap(),
+            vec![genesis.block_hash(), block1.block_hash()]
+        );
+
+        // Blocks extending the tip are scanned on the next run
+        write_block_file(&dir.join("blk00001.dat"), &[&block2], [0; XOR_KEY_LEN]);
+        assert_eq!(scan(&mut index).unwrap(), vec![block2.block_hash()]);
+        assert_eq!(index.tip, Some((2, block2.block_hash())));
+
+        // After a reorg the index holds outputs of stale blocks, so it cannot be resumed
+        write_block_file(
+            &dir.join("blk00002.dat"),
+            &[&fork1, &fork2, &fork3],
+            [0; XOR_KEY_LEN],
+        );
+        let err = scan(&mut index).unwrap_err();
+        assert!(err.contains("no longer in the best chain"), "{}", err);
+        assert_eq!(index.tip, Some((2, block2.block_hash())));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_xor_key() {
+        let dir = std::env::temp_dir().join(format!("wallet-blocks-xor-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("xor.dat"), [0u8; 4]).unwrap();
+
+        let err = BlockFiles::open(&dir, Network::Regtest).unwrap_err();
+        assert!(err.contains("invalid xor.dat"), "{}", err);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_block_file() {
+        let genesis = block(BlockHash::all_zeros(), 0, vec![]);
+        let block1 = block(genesis.block_hash(), 1, vec![]);
+        let dir = std::env::temp_dir().join(format!("wallet-blocks-cut-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("blk00000.dat");
+        write_block_file(&path, &[&genesis, &block1], [0; XOR_KEY_LEN]);
+        let data = fs::read(&path).unwrap();
+        let block1_start = 8 + serialize(&genesis).len();
+
+        // Cut in the middle of the last block, and right after its record
+        for cut in [data.len() - 64 - 10, block1_start + 8] {
+            fs::write(&path, &data[..cut]).unwrap();
+            let files = BlockFiles::open(&dir, Network::Regtest).unwrap();
+            let blocks = files
+                .blocks()
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(blocks, vec![genesis.clone()], "cut at {}", cut);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
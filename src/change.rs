@@ -0,0 +1,422 @@
+use std::collections::HashSet;
+
+use bitcoin::{transaction::Version, OutPoint, Transaction};
+
+use crate::{
+    denomination::get_output_amounts,
+    global::{is_anti_fee_sniping, signals_rbf},
+    input::{get_input_types, low_order_r_grinding},
+    output::{
+        get_output_types, optimal_change_candidates, unnecessary_input_change_candidates,
+        ChangeIndex,
+    },
+    util::TxOutWithOutpoint,
+};
+
+/// Minimum probability an output needs before we call it the change output
+const CHANGE_PROBABILITY_THRESHOLD: f32 = 0.55;
+/// Minimum fraction of matching fingerprint features between a transaction and the transaction
+/// spending one of its outputs before we consider them to be made by the same wallet
+const FINGERPRINT_CONSISTENCY_THRESHOLD: f32 = 0.8;
+
+/// Signals that an output is the change output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Enum))]
+pub enum ChangeSignal {
+    /// Output is the same type as all of the inputs
+    ScriptType,
+    /// Output reuses an input script pubkey
+    AddressReuse,
+    /// Output amount is not round
+    RoundAmount,
+    /// The payment could not have been this output without an unnecessary input
+    UnnecessaryInput,
+    /// Output is smaller than every input
+    OptimalChange,
+    /// Output is the last output
+    Position,
+    /// Output is spent by a transaction with the same fingerprint
+    FingerprintConsistency,
+}
+
+impl ChangeSignal {
+    /// Log-odds weight of the signal. Stronger signals outweigh weaker conflicting ones.
+    fn weight(&self) -> f32 {
+        match self {
+            ChangeSignal::ScriptType => 2.0,
+            ChangeSignal::AddressReuse => 1.5,
+            ChangeSignal::RoundAmount => 1.0,
+            ChangeSignal::UnnecessaryInput => 0.75,
+            ChangeSignal::OptimalChange => 0.5,
+            ChangeSignal::Position => 0.15,
+            ChangeSignal::FingerprintConsistency => 1.5,
+        }
+    }
+}
+
+/// Extra context for change detection that is not part of the transaction itself
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ChangeHints<'a> {
+    /// The fiat price of one BTC, used to treat fiat round amounts as payments
+    pub(crate) exchange_rate: Option<f64>,
+    /// Transactions spending the outputs of the transaction
+    pub(crate) spending_txs: &'a [Transaction],
+}
+
+/// Probability that an output is the change output, and the signals that fired for it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct OutputChangeProbability {
+    /// Probability the output is the change output, between 0 and 1
+    pub probability: f32,
+    /// The signals that fired for this output
+    pub signals: Vec<ChangeSignal>,
+}
+
+/// Result of running every change signal over a transaction
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct ChangeDetection {
+    /// Change probability of each output, in output order
+    pub outputs: Vec<OutputChangeProbability>,
+}
+
+impl ChangeDetection {
+    /// Derives a change index, only concluding when one output is sufficiently more likely than the rest
+    pub fn change_index(&self) -> ChangeIndex {
+        if self.outputs.len() == 1 {
+            return ChangeIndex::NoChange;
+        }
+
+        let Some((idx, most_likely)) = self
+            .outputs
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.probability.total_cmp(&b.probability))
+        else {
+            return ChangeIndex::Inconclusive;
+        };
+        // Probabilities sum to 1, so at most one output can be above the threshold
+        if most_likely.probability > CHANGE_PROBABILITY_THRESHOLD {
+            return ChangeIndex::Found(idx);
+        }
+
+        ChangeIndex::Inconclusive
+    }
+
+    /// Returns every signal that fired for any output
+    pub fn signals(&self) -> HashSet<ChangeSignal> {
+        self.outputs
+            .iter()
+            .flat_map(|output| output.signals.iter().copied())
+            .collect()
+    }
+}
+
+/// Outputs that are the same type as all of the inputs
+fn script_type_candidates(tx: &Transaction, prev_outs: &[TxOutWithOutpoint]) -> Vec<usize> {
+    let input_types = get_input_types(tx, prev_outs);
+    let Some(first_type) = input_types.first() else {
+        return vec![];
+    };
+    if !input_types.iter().all(|t| t == first_type) {
+        return vec![];
+    }
+
+    get_output_types(tx)
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| *t == first_type)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Outputs that reuse an input script pubkey
+fn address_reuse_candidates(tx: &Transaction, prev_outs: &[TxOutWithOutpoint]) -> Vec<usize> {
+    let input_scripts: HashSet<_> = prev_outs
+        .iter()
+        .map(|txout| &txout.txout.script_pubkey)
+        .collect();
+
+    tx.output
+        .iter()
+        .enumerate()
+        .filter(|(_, txout)| input_scripts.contains(&txout.script_pubkey))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Outputs with non-round amounts
+fn round_amount_candidates(tx: &Transaction, exchange_rate: Option<f64>) -> Vec<usize> {
+    get_output_amounts(tx, exchange_rate)
+        .iter()
+        .enumerate()
+        .filter(|(_, amount)| !amount.is_round())
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Fingerprint features that can be read from a transaction without knowing its prevouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SpendingFingerprint {
+    version: Version,
+    anti_fee_snipe: bool,
+    signals_rbf: bool,
+    low_r_grinding: bool,
+}
+
+impl SpendingFingerprint {
+    fn new(tx: &Transaction) -> Self {
+        Self {
+            version: tx.version,
+            anti_fee_snipe: is_anti_fee_sniping(tx),
+            signals_rbf: signals_rbf(tx),
+            low_r_grinding: low_order_r_grinding(tx),
+        }
+    }
+
+    /// Returns the matching features between the two fingerprints
+    fn matches(&self, other: &SpendingFingerprint) -> [bool; 4] {
+        [
+            self.version == other.version,
+            self.anti_fee_snipe == other.anti_fee_snipe,
+            self.signals_rbf == other.signals_rbf,
+            self.low_r_grinding == other.low_r_grinding,
+        ]
+    }
+}
+
+/// Outputs spent by a transaction that shares the fingerprint of this transaction.
+/// Change is spent by the same wallet, so its spending transaction should look alike.
+fn fingerprint_consistency_candidates(
+    tx: &Transaction,
+    prev_outs: &[TxOutWithOutpoint],
+    spending_txs: &[Transaction],
+) -> Vec<usize> {
+    if spending_txs.is_empty() {
+        return vec![];
+    }
+
+    let fingerprint = SpendingFingerprint::new(tx);
+    let input_types = get_input_types(tx, prev_outs);
+    // The wallet's own script type, if all of the inputs agree on one
+    let wallet_type = input_types
+        .first()
+        .filter(|first| input_types.iter().all(|t| t == *first));
+    let txid = tx.compute_txid();
+
+    (0..tx.output.len())
+        .filter(|idx| {
+            let outpoint = OutPoint::new(txid, *idx as u32);
+            let Some(spending_tx) = spending_txs.iter().find(|spending_tx| {
+                spending_tx
+                    .input
+                    .iter()
+                    .any(|txin| txin.previous_output == outpoint)
+            }) else {
+                return false;
+            };
+
+            let mut matches = fingerprint
+                .matches(&SpendingFingerprint::new(spending_tx))
+                .to_vec();
+            if let Some(wallet_type) = wallet_type {
+                // The child's change should go back to the same script type
+                matches.push(get_output_types(spending_tx).contains(wallet_type));
+            }
+            let similarity = matches.iter().filter(|m| **m).count() as f32 / matches.len() as f32;
+            similarity >= FINGERPRINT_CONSISTENCY_THRESHOLD
+        })
+        .collect()
+}
+
+/// Runs every change signal over the transaction and scores each output
+pub(crate) fn detect_change(
+    tx: &Transaction,
+    prev_outs: &[TxOutWithOutpoint],
+    hints: &ChangeHints,
+) -> ChangeDetection {
+    let mut signals = vec![Vec::new(); tx.output.len()];
+    let mut fire = |signal: ChangeSignal, candidates: Vec<usize>| {
+        for idx in candidates {
+            signals[idx].push(signal);
+        }
+    };
+
+    if tx.output.len() > 1 {
+        fire(
+            ChangeSignal::ScriptType,
+            script_type_candidates(tx, prev_outs),
+        );
+        fire(
+            ChangeSignal::AddressReuse,
+            address_reuse_candidates(tx, prev_outs),
+        );
+        fire(
+            ChangeSignal::RoundAmount,
+            round_amount_candidates(tx, hints.exchange_rate),
+        );
+        fire(
+            ChangeSignal::UnnecessaryInput,
+            unnecessary_input_change_candidates(tx, prev_outs),
+        );
+        fire(
+            ChangeSignal::OptimalChange,
+            optimal_change_candidates(tx, prev_outs),
+        );
+        fire(ChangeSignal::Position, vec![tx.output.len() - 1]);
+        fire(
+            ChangeSignal::FingerprintConsistency,
+            fingerprint_consistency_candidates(tx, prev_outs, hints.spending_txs),
+        );
+    }
+
+    // Softmax over the outputs, so a signal that fires for every output carries no information
+    let scores = signals
+        .iter()
+        .map(|signals| {
+            signals
+                .iter()
+                .map(|signal| signal.weight())
+                .sum::<f32>()
+                .exp()
+        })
+        .collect::<Vec<_>>();
+    let total = scores.iter().sum::<f32>();
+
+    ChangeDetection {
+        outputs: signals
+            .into_iter()
+            .zip(scores)
+            .map(|(signals, score)| OutputChangeProbability {
+                probability: score / total,
+                signals,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{payment, ScriptType};
+    use bitcoin::{
+        hashes::Hash, Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, WPubkeyHash, Witness,
+    };
+
+    #[test]
+    fn test_single_output_has_no_change() {
+        let (tx, prev_outs) =
+            payment(&[10_000], &[(9_000, ScriptType::P2shP2wpkh)]).build_with_prevouts();
+        let detection = detect_change(&tx, &prev_outs, &ChangeHints::default());
+        assert_eq!(detection.outputs.len(), 1);
+        assert!(detection.signals().is_empty());
+        assert_eq!(detection.change_index(), ChangeIndex::NoChange);
+    }
+
+    #[test]
+    fn test_probabilities_sum_to_one() {
+        let (tx, prev_outs) = payment(
+            &[10_000, 20_000],
+            &[
+                (10_000, ScriptType::P2shP2wpkh),
+                (15_123, ScriptType::P2wpkh),
+                (3_000, ScriptType::P2shP2wpkh),
+            ],
+        )
+        .build_with_prevouts();
+        let detection = detect_change(&tx, &prev_outs, &ChangeHints::default());
+        let total = detection.outputs.iter().map(|o| o.probability).sum::<f32>();
+        assert!((total - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_signals_reported_per_output() {
+        let (tx, prev_outs) = payment(
+            &[30_000],
+            &[
+                (12_345, ScriptType::P2wpkh),
+                (17_000, ScriptType::P2shP2wpkh),
+            ],
+        )
+        .build_with_prevouts();
+        let detection = detect_change(&tx, &prev_outs, &ChangeHints::default());
+        assert_eq!(
+            detection.outputs[0].signals,
+            vec![
+                ChangeSignal::ScriptType,
+                ChangeSignal::RoundAmount,
+                ChangeSignal::OptimalChange
+            ]
+        );
+        assert_eq!(
+            detection.outputs[1].signals,
+            vec![ChangeSignal::OptimalChange, ChangeSignal::Position]
+        );
+        assert_eq!(detection.change_index(), ChangeIndex::Found(0));
+    }
+
+    #[test]
+    fn test_conflicting_signals_are_weighed() {
+        // Output 0 matches the input type, but output 1 reuses an input script, is not round,
+        // is smaller than every input and is last
+        let (tx, prev_outs) = payment(&[10_000, 20_000], &[(25_000, ScriptType::P2wpkh)])
+            .change(Amount::from_sat(4_321), ScriptType::P2wpkh)
+            .change_reuses_input_address(true)
+            .build_with_prevouts();
+        let detection = detect_change(&tx, &prev_outs, &ChangeHints::default());
+        assert!(detection.outputs[0]
+            .signals
+            .contains(&ChangeSignal::ScriptType));
+        assert!(detection.outputs[1]
+            .signals
+            .contains(&ChangeSignal::AddressReuse));
+        assert_eq!(detection.change_index(), ChangeIndex::Found(1));
+    }
+
+    #[test]
+    fn test_fingerprint_consistency() {
+        let (tx, prev_outs) = payment(
+            &[30_000],
+            &[(12_345, ScriptType::P2wpkh), (17_321, ScriptType::P2wpkh)],
+        )
+        .build_with_prevouts();
+        assert_eq!(
+            detect_change(&tx, &prev_outs, &ChangeHints::default()).change_index(),
+            ChangeIndex::Inconclusive
+        );
+
+        let txid = tx.compute_txid();
+        let spend = |vout: u32, version: Version, sequence: Sequence| Transaction {
+            version,
+            lock_time: bitcoin::locktime::absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(txid, vout),
+                script_sig: ScriptBuf::new(),
+                sequence,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([7; 20])),
+            }],
+        };
+        // Output 0 is spent by a transaction that looks like this one, output 1 is not
+        let spending_txs = vec![
+            spend(0, Version::TWO, Sequence::MAX),
+            spend(1, Version::ONE, Sequence::ENABLE_RBF_NO_LOCKTIME),
+        ];
+        let hints = ChangeHints {
+            spending_txs: &spending_txs,
+            ..Default::default()
+        };
+        let detection = detect_change(&tx, &prev_outs, &hints);
+        assert!(detection.outputs[0]
+            .signals
+            .contains(&ChangeSignal::FingerprintConsistency));
+        assert!(!detection.outputs[1]
+            .signals
+            .contains(&ChangeSignal::FingerprintConsistency));
+        assert_eq!(detection.change_index(), ChangeIndex::Found(0));
+    }
+}
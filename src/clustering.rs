@@ -0,0 +1,381 @@
+//! Unsupervised clustering of transaction fingerprints into candidate wallet families.
+//! Most transactions do not match any of the known wallets, so we group them by how similar
+//! their heuristics are and report the traits each group has in common.
+
+use std::collections::{BTreeSet, HashMap};
+
+use bitcoin::Txid;
+
+use crate::{
+    fingerprint::{
+        change_tag, input_order_tag, locktime_tag, output_structure_tag, sequence_tag, sighash_tag,
+    },
+    heuristics::Heuristics,
+};
+
+/// Value of a trait that cannot be determined yet, such as the signatures of an unsigned PSBT
+const UNKNOWN: &str = "unknown";
+
+/// Value of a single trait of a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum TraitValue {
+    Single(String),
+    Set(BTreeSet<String>),
+}
+
+impl TraitValue {
+    fn single(value: impl ToString) -> Self {
+        TraitValue::Single(value.to_string())
+    }
+
+    fn set<T: ToString>(values: impl IntoIterator<Item = T>) -> Self {
+        TraitValue::Set(values.into_iter().map(|v| v.to_string()).collect())
+    }
+
+    /// Distance between two values of the same trait, between 0 and 1.
+    /// Sets use the Jaccard distance so partially overlapping sets are not maximally distant.
+    fn distance(&self, other: &TraitValue) -> f32 {
+        match (self, other) {
+            (TraitValue::Single(a), TraitValue::Single(b)) => f32::from(u8::from(a != b)),
+            (TraitValue::Set(a), TraitValue::Set(b)) => {
+                let union = a.union(b).count();
+                if union == 0 {
+                    return 0.0;
+                }
+                1.0 - a.intersection(b).count() as f32 / union as f32
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+impl std::fmt::Display for TraitValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraitValue::Single(value) => write!(f, "{}", value),
+            TraitValue::Set(values) if values.is_empty() => write!(f, "-"),
+            TraitValue::Set(values) => {
+                write!(
+                    f,
+                    "{}",
+                    values.iter().cloned().collect::<Vec<_>>().join(",")
+                )
+            }
+        }
+    }
+}
+
+/// The traits of a transaction we cluster on, in a fixed order
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Traits(Vec<(&'static str, TraitValue)>);
+
+impl Traits {
+    pub(crate) fn new(heuristics: &Heuristics) -> Self {
+        Self(vec![
+            ("version", TraitValue::single(heuristics.tx_version.0)),
+            (
+                "locktime",
+                TraitValue::single(locktime_tag(heuristics.locktime_class)),
+            ),
+            (
+                "sequence",
+                TraitValue::set(heuristics.sequence_profile.iter().map(|s| sequence_tag(*s))),
+            ),
+            (
+                "input_types",
+                TraitValue::set(heuristics.input_types.iter()),
+            ),
+            (
+                "output_types",
+                TraitValue::set(heuristics.output_types.iter()),
+            ),
+            (
+                "input_order",
+                TraitValue::set(heuristics.input_order.iter().map(|o| input_order_tag(*o))),
+            ),
+            (
+                "output_order",
+                TraitValue::set(
+                    heuristics
+                        .output_structure
+                        .iter()
+                        .filter_map(|o| output_structure_tag(*o)),
+                ),
+            ),
+            (
+                "change",
+                TraitValue::single(change_tag(
+                    heuristics.change_index,
+                    heuristics.output_types.len(),
+                )),
+            ),
+            (
+                "low_r",
+                match heuristics.low_r_grinding {
+                    Some(low_r) => TraitValue::single(low_r),
+                    None => TraitValue::single(UNKNOWN),
+                },
+            ),
+            (
+                "sighash",
+                match &heuristics.sighash_types {
+                    Some(sighash_types) => {
+                        TraitValue::set(sighash_types.iter().map(|s| sighash_tag(*s)))
+                    }
+                    None => TraitValue::single(UNKNOWN),
+                },
+            ),
+            (
+                "mixed_input_types",
+                TraitValue::single(heuristics.mixed_input_types),
+            ),
+            (
+                "address_reuse",
+                TraitValue::single(heuristics.address_reuse),
+            ),
+            (
+                "uncompressed_pubkey",
+                TraitValue::single(heuristics.spending_spk_has_uncompressed_pubkey),
+            ),
+        ])
+    }
+
+    /// Returns the value of the named trait, e.g. `2` for `version`
+    pub(crate) fn get(&self, name: &str) -> Option<String> {
+        self.0
+            .iter()
+            .find(|(trait_name, _)| *trait_name == name)
+            .map(|(_, value)| value.to_string())
+    }
+
+    /// Returns the predicates that hold for these traits,
+    /// `name=value` for single valued traits and `name~member` for each member of set traits
+    pub(crate) fn predicates(&self) -> BTreeSet<String> {
+        self.0
+            .iter()
+            .flat_map(|(name, value)| match value {
+                TraitValue::Single(value) => vec![format!("{}={}", name, value)],
+                TraitValue::Set(values) => values
+                    .iter()
+                    .map(|value| format!("{}~{}", name, value))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Mean distance over all traits, between 0 and 1
+    fn distance(&self, other: &Traits) -> f32 {
+        let total = self
+            .0
+            .iter()
+            .zip(other.0.iter())
+            .map(|((_, a), (_, b))| a.distance(b))
+            .sum::<f32>();
+        total / self.0.len() as f32
+    }
+}
+
+/// Distance between the heuristics of two transactions, between 0 (identical) and 1
+pub fn distance(a: &Heuristics, b: &Heuristics) -> f32 {
+    Traits::new(a).distance(&Traits::new(b))
+}
+
+/// Parameters for clustering heuristics into wallet families
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct ClusteringConfig {
+    /// Maximum distance between a transaction and the most common fingerprint of its family
+    pub max_distance: f32,
+    /// Families with fewer transactions than this are not reported
+    pub min_family_size: u64,
+    /// Number of example txids reported per family
+    pub max_examples: u64,
+}
+
+impl Default for ClusteringConfig {
+    fn default() -> Self {
+        Self {
+            max_distance: 0.15,
+            min_family_size: 10,
+            max_examples: 5,
+        }
+    }
+}
+
+/// A candidate wallet family: a group of transactions with similar fingerprints
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct WalletFamily {
+    /// Traits shared by every transaction in the family, e.g. `version=2`
+    pub defining_traits: Vec<String>,
+    /// Number of transactions in the family
+    pub size: u64,
+    /// Example transactions from the family, most representative first
+    pub example_txids: Vec<String>,
+}
+
+struct Cluster {
+    leader: Traits,
+    members: Vec<(Traits, Vec<Txid>)>,
+}
+
+/// Clusters the heuristics of many transactions into candidate wallet families, largest first.
+///
+/// Transactions with identical traits are grouped first. Then, from the most common traits down,
+/// each group joins the first family whose most common traits are within `max_distance`,
+/// or starts a new family.
+pub fn cluster_wallet_families(
+    heuristics: &[Heuristics],
+    config: &ClusteringConfig,
+) -> Vec<WalletFamily> {
+    let mut groups: HashMap<Traits, Vec<Txid>> = HashMap::new();
+    for h in heuristics {
+        groups.entry(Traits::new(h)).or_default().push(h.txid);
+    }
+    let mut groups = groups.into_iter().collect::<Vec<_>>();
+    // Largest groups lead, ties broken by txid so the result is deterministic
+    groups.sort_by(|(_, a), (_, b)| {
+        b.len()
+            .cmp(&a.len())
+            .then_with(|| a.iter().min().cmp(&b.iter().min()))
+    });
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for (traits, txids) in groups {
+        match clusters
+            .iter_mut()
+            .find(|cluster| cluster.leader.distance(&traits) <= config.max_distance)
+        {
+            Some(cluster) => cluster.members.push((traits, txids)),
+            None => clusters.push(Cluster {
+                leader: traits.clone(),
+                members: vec![(traits, txids)],
+            }),
+        }
+    }
+
+    let mut families = clusters
+        .into_iter()
+        .map(|cluster| {
+            let size = cluster
+                .members
+                .iter()
+                .map(|(_, txids)| txids.len() as u64)
+                .sum::<u64>();
+            let defining_traits = cluster
+                .leader
+                .0
+                .iter()
+                .enumerate()
+                .filter(|(i, (_, value))| {
+                    cluster
+                        .members
+                        .iter()
+                        .all(|(traits, _)| traits.0[*i].1 == *value)
+                })
+                .map(|(_, (name, value))| format!("{}={}", name, value))
+                .collect();
+            let example_txids = cluster
+                .members
+                .iter()
+                .flat_map(|(_, txids)| txids.iter())
+                .take(config.max_examples as usize)
+                .map(|txid| txid.to_string())
+                .collect();
+            WalletFamily {
+                defining_traits,
+                size,
+                example_txids,
+            }
+        })
+        .filter(|family| family.size >= config.min_family_size)
+        .collect::<Vec<_>>();
+    families.sort_by_key(|family| std::cmp::Reverse(family.size));
+    families
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{change::ChangeHints, test_utils::p2sh_payment, util::get_prevouts};
+    use bitcoin::{transaction::Version, Sequence};
+
+    /// A transaction spending a p2wpkh output, paying to a p2sh output with change back to p2wpkh
+    fn heuristics(seed: u64, version: Version, sequence: Sequence, reuse: bool) -> Heuristics {
+        let (tx, prev_txs) = p2sh_payment(seed)
+            .version(version)
+            .sequence(sequence)
+            .change_reuses_input_address(reuse)
+            .build();
+        let prev_outs = get_prevouts(&tx, &prev_txs).unwrap();
+        Heuristics::from_prevouts(&tx, &prev_outs, &ChangeHints::default())
+    }
+
+    #[test]
+    fn test_distance() {
+        let a = heuristics(1, Version::TWO, Sequence::ENABLE_RBF_NO_LOCKTIME, false);
+        let b = heuristics(2, Version::TWO, Sequence::ENABLE_RBF_NO_LOCKTIME, false);
+        let c = heuristics(3, Version::ONE, Sequence::MAX, false);
+        assert_eq!(distance(&a, &b), 0.0);
+        assert!(distance(&a, &c) > 0.0);
+        assert_eq!(distance(&a, &c), distance(&c, &a));
+    }
+
+    #[test]
+    fn test_cluster_wallet_families() {
+        let mut all = Vec::new();
+        for seed in 0..12 {
+            all.push(heuristics(
+                seed,
+                Version::TWO,
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+                false,
+            ));
+        }
+        // Differs from the first family in address reuse only
+        for seed in 20..31 {
+            all.push(heuristics(
+                seed,
+                Version::TWO,
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+                true,
+            ));
+        }
+        for seed in 40..50 {
+            all.push(heuristics(seed, Version::ONE, Sequence::MAX, false));
+        }
+        // Too few to be reported
+        for seed in 60..62 {
+            all.push(heuristics(
+                seed,
+                Version::non_standard(3),
+                Sequence::ZERO,
+                true,
+            ));
+        }
+
+        let families = cluster_wallet_families(&all, &ClusteringConfig::default());
+        assert_eq!(families.len(), 2);
+
+        assert_eq!(families[0].size, 23);
+        assert!(families[0]
+            .defining_traits
+            .contains(&"version=2".to_string()));
+        assert!(families[0]
+            .defining_traits
+            .contains(&"sequence=rbf".to_string()));
+        assert!(!families[0]
+            .defining_traits
+            .iter()
+            .any(|t| t.starts_with("address_reuse")));
+        assert_eq!(families[0].example_txids.len(), 5);
+
+        assert_eq!(families[1].size, 10);
+        assert!(families[1]
+            .defining_traits
+            .contains(&"version=1".to_string()));
+        assert!(families[1]
+            .defining_traits
+            .contains(&"sequence=final".to_string()));
+    }
+}
@@ -0,0 +1,220 @@
+use bitcoin::Transaction;
+
+use crate::{
+    fee::get_fee,
+    input::{get_input_types, inputs_oldest_first},
+    output::{get_change_index, ChangeIndex},
+    util::{get_output_type, TxOutWithOutpoint},
+};
+
+/// Minimum number of inputs swept into a single output before we call it a consolidation
+const CONSOLIDATION_MIN_INPUTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Enum))]
+pub enum CoinSelectionAlgorithm {
+    /// Changeless exact match, as produced by Bitcoin Core's Branch and Bound
+    BranchAndBound,
+    /// Inputs drawn at random until the target is met, leaving change
+    SingleRandomDraw,
+    /// Largest UTXOs picked first until the target is met
+    LargestFirst,
+    /// Oldest UTXOs picked first until the target is met,
+    /// only detected when the confirmation heights of the inputs are known
+    OldestFirst,
+    /// Many inputs swept into a single output of their own script type
+    Consolidation,
+    /// Coin selection algorithm is unknown
+    Unknown,
+}
+
+/// The inferred coin selection algorithm and how confident we are in it
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct CoinSelection {
+    pub algorithm: CoinSelectionAlgorithm,
+    /// Confidence in the inferred algorithm, between 0 and 1
+    pub confidence: f32,
+}
+
+impl CoinSelection {
+    fn new(algorithm: CoinSelectionAlgorithm, confidence: f32) -> Self {
+        Self {
+            algorithm,
+            confidence,
+        }
+    }
+
+    fn unknown() -> Self {
+        Self::new(CoinSelectionAlgorithm::Unknown, 0.0)
+    }
+}
+
+/// Infers the coin selection algorithm from the change output and how the inputs relate to the payment
+pub(crate) fn get_coin_selection(
+    tx: &Transaction,
+    prev_outs: &[TxOutWithOutpoint],
+) -> CoinSelection {
+    let input_amounts = prev_outs
+        .iter()
+        .map(|prev_out| prev_out.txout.value)
+        .collect::<Vec<_>>();
+    let Some(smallest_input) = input_amounts.iter().min().copied() else {
+        return CoinSelection::unknown();
+    };
+    let Some(fee) = get_fee(tx, prev_outs) else {
+        return CoinSelection::unknown();
+    };
+
+    match get_change_index(tx, prev_outs) {
+        ChangeIndex::NoChange => {
+            // Sweeping to a script of an input's type is most likely sending to ourselves,
+            // while a changeless payment to another script type is an exact match
+            let input_types = get_input_types(tx, prev_outs);
+            let output_type = get_output_type(&tx.output[0]);
+            if input_amounts.len() >= CONSOLIDATION_MIN_INPUTS && input_types.contains(&output_type)
+            {
+                let confidence = if input_types.iter().all(|t| *t == output_type) {
+                    0.8
+                } else {
+                    0.5
+                };
+                return CoinSelection::new(CoinSelectionAlgorithm::Consolidation, confidence);
+            }
+
+            // BnB never selects an input that only pays for fees
+            let confidence = if smallest_input > fee { 0.6 } else { 0.2 };
+            CoinSelection::new(CoinSelectionAlgorithm::BranchAndBound, confidence)
+        }
+        ChangeIndex::Found(idx) => {
+            let change = tx.output[idx].value;
+            // If the change covers the smallest input, that input was not needed to fund the payment.
+            // Largest-first stops as soon as the target is met so it never selects unnecessary inputs.
+            if smallest_input <= change {
+                return CoinSelection::new(CoinSelectionAlgorithm::SingleRandomDraw, 0.6);
+            }
+            if input_amounts.len() == 1 {
+                return CoinSelection::unknown();
+            }
+
+            if input_amounts.windows(2).all(|w| w[0] >= w[1]) {
+                return CoinSelection::new(CoinSelectionAlgorithm::LargestFirst, 0.7);
+            }
+            if inputs_oldest_first(tx, prev_outs) {
+                return CoinSelection::new(CoinSelectionAlgorithm::OldestFirst, 0.6);
+            }
+            CoinSelection::unknown()
+        }
+        ChangeIndex::Inconclusive => CoinSelection::unknown(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{payment, ScriptType};
+
+    #[test]
+    fn test_consolidation() {
+        let (tx, prev_outs) = payment(&[10_000, 20_000, 30_000], &[(59_000, ScriptType::P2wpkh)])
+            .build_with_prevouts();
+        let coin_selection = get_coin_selection(&tx, &prev_outs);
+        assert_eq!(
+            coin_selection.algorithm,
+            CoinSelectionAlgorithm::Consolidation
+        );
+        assert_eq!(coin_selection.confidence, 0.8);
+    }
+
+    #[test]
+    fn test_changeless_payment_is_not_consolidation() {
+        // Three inputs paying exactly to a script type none of them has
+        let (tx, prev_outs) = payment(
+            &[10_000, 20_000, 30_000],
+            &[(59_000, ScriptType::P2shP2wpkh)],
+        )
+        .build_with_prevouts();
+        let coin_selection = get_coin_selection(&tx, &prev_outs);
+        assert_eq!(
+            coin_selection.algorithm,
+            CoinSelectionAlgorithm::BranchAndBound
+        );
+    }
+
+    #[test]
+    fn test_branch_and_bound() {
+        let (tx, prev_outs) =
+            payment(&[10_000, 20_000], &[(29_500, ScriptType::P2shP2wpkh)]).build_with_prevouts();
+        let coin_selection = get_coin_selection(&tx, &prev_outs);
+        assert_eq!(
+            coin_selection.algorithm,
+            CoinSelectionAlgorithm::BranchAndBound
+        );
+    }
+
+    #[test]
+    fn test_single_random_draw() {
+        // Change of 15_000 covers the 10_000 input, so it was not needed
+        let (tx, prev_outs) = payment(
+            &[10_000, 20_000],
+            &[
+                (14_000, ScriptType::P2shP2wpkh),
+                (15_000, ScriptType::P2wpkh),
+            ],
+        )
+        .build_with_prevouts();
+        let coin_selection = get_coin_selection(&tx, &prev_outs);
+        assert_eq!(
+            coin_selection.algorithm,
+            CoinSelectionAlgorithm::SingleRandomDraw
+        );
+    }
+
+    #[test]
+    fn test_largest_first() {
+        let (tx, prev_outs) = payment(
+            &[20_000, 10_000],
+            &[
+                (24_000, ScriptType::P2shP2wpkh),
+                (5_000, ScriptType::P2wpkh),
+            ],
+        )
+        .build_with_prevouts();
+        let coin_selection = get_coin_selection(&tx, &prev_outs);
+        assert_eq!(
+            coin_selection.algorithm,
+            CoinSelectionAlgorithm::LargestFirst
+        );
+        assert_eq!(coin_selection.confidence, 0.7);
+    }
+
+    #[test]
+    fn test_oldest_first() {
+        let (tx, mut prev_outs) = payment(
+            &[10_000, 20_000],
+            &[
+                (24_000, ScriptType::P2shP2wpkh),
+                (5_000, ScriptType::P2wpkh),
+            ],
+        )
+        .build_with_prevouts();
+        assert_eq!(
+            get_coin_selection(&tx, &prev_outs).algorithm,
+            CoinSelectionAlgorithm::Unknown
+        );
+
+        prev_outs[0].height = Some(100);
+        prev_outs[1].height = Some(200);
+        assert_eq!(
+            get_coin_selection(&tx, &prev_outs).algorithm,
+            CoinSelectionAlgorithm::OldestFirst
+        );
+
+        // Inputs from the same block carry no ordering
+        prev_outs[1].height = Some(100);
+        assert_eq!(
+            get_coin_selection(&tx, &prev_outs).algorithm,
+            CoinSelectionAlgorithm::Unknown
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! Conformance with the reference Python implementation this crate is ported from,
+//! [`fingerprinting.py`](https://github.com/ishaanam/wallet-fingerprinting/blob/master/fingerprinting.py).
+//!
+//! Wallet fixtures (see [`crate::fixtures`]) may carry the outputs of the reference implementation
+//! for their transaction, written by `fixtures/generate_reference.py`:
+//!
+//! ```json
+//! {
+//!   "reference": { "wallets": "electrum", "signals_rbf": "true", "change_index": "-2" },
+//!   "divergences": { "change_index": "the payment is a round amount, so the other output is change" }
+//! }
+//! ```
+//!
+//! The reference must report every heuristic in [`REFERENCE_HEURISTICS`], and each must agree with
+//! this crate, unless it is listed in `divergences` together with the reason we intentionally differ.
+//! A listed divergence that no longer differs is reported too, so the list stays accurate.
+//!
+//! Intentional divergences:
+//! - `change_index`: change is detected by combining weighted signals
+//!   (see [`crate::heuristics::Heuristics::change_detection`]) rather than by the first
+//!   heuristic that matches, so transactions the reference finds inconclusive may have a change
+//!   output here, and the other way around.
+
+use std::collections::BTreeMap;
+
+use bitcoin::Transaction;
+
+use crate::{
+    change::ChangeHints,
+    detect_wallet,
+    fixtures::Fixture,
+    heuristics::Heuristics,
+    output::ChangeIndex,
+    util::{get_prevouts, OutputType},
+};
+
+/// Heuristics compared with the reference implementation, in the vocabulary of this crate
+pub const REFERENCE_HEURISTICS: [&str; 12] = [
+    "wallets",
+    "version",
+    "anti_fee_sniping",
+    "uncompressed_pubkeys",
+    "signals_rbf",
+    "low_r",
+    "address_reuse",
+    "input_types",
+    "output_types",
+    "input_order",
+    "output_structure",
+    "change_index",
+];
+
+/// Sorted, comma separated, lowercase names of a list of enum values
+fn sorted_list(values: impl IntoIterator<Item = String>) -> String {
+    let mut values = values
+        .into_iter()
+        .map(|value| value.to_lowercase())
+        .collect::<Vec<_>>();
+    values.sort();
+    values.join(",")
+}
+
+/// Runs this crate over a transaction, reporting each of [`REFERENCE_HEURISTICS`] the way the
+/// reference implementation fixtures do
+pub fn reference_heuristics(
+    tx: &Transaction,
+    prev_txs: &[Transaction],
+) -> Result<BTreeMap<String, String>, String> {
+    let heuristics =
+        Heuristics::from_prevouts(tx, &get_prevouts(tx, prev_txs)?, &ChangeHints::default());
+    let (wallets, _) = detect_wallet(tx, prev_txs)?;
+    let list = |values: &[OutputType]| {
+        values
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    // The reference uses -1 for a single output and -2 when inconclusive
+    let change_index = match heuristics.change_index {
+        ChangeIndex::NoChange => "-1".to_string(),
+        ChangeIndex::Inconclusive => "-2".to_string(),
+        ChangeIndex::Found(index) => index.to_string(),
+    };
+
+    Ok(BTreeMap::from([
+        (
+            "wallets".to_string(),
+            sorted_list(wallets.iter().map(|wallet| wallet.to_string())),
+        ),
+        ("version".to_string(), heuristics.tx_version.0.to_string()),
+        (
+            "anti_fee_sniping".to_string(),
+            heuristics.anti_fee_snipe.to_string(),
+        ),
+        (
+            "uncompressed_pubkeys".to_string(),
+            heuristics.spending_spk_has_uncompressed_pubkey.to_string(),
+        ),
+        (
+            "signals_rbf".to_string(),
+            heuristics.signals_rbf.to_string(),
+        ),
+        (
+            "low_r".to_string(),
+            heuristics
+                .low_r_grinding
+                .map_or("unknown".to_string(), |low_r| low_r.to_string()),
+        ),
+        (
+            "address_reuse".to_string(),
+            heuristics.address_reuse.to_string(),
+        ),
+        ("input_types".to_string(), list(&heuristics.input_types)),
+        ("output_types".to_string(), list(&heuristics.output_types)),
+        (
+            "input_order".to_string(),
+            sorted_list(
+                heuristics
+                    .input_order
+                    .iter()
+                    .map(|order| format!("{:?}", order)),
+            ),
+        ),
+        (
+            "output_structure".to_string(),
+            sorted_list(
+                heuristics
+                    .output_structure
+                    .iter()
+                    .map(|structure| format!("{:?}", structure)),
+            ),
+        ),
+        ("change_index".to_string(), change_index),
+    ]))
+}
+
+/// Compares the fixture with the reference outputs it carries,
+/// returning a description of every undocumented disagreement and every stale divergence
+pub fn check_conformance(fixture: &Fixture) -> Vec<String> {
+    let mut problems = Vec::new();
+    let actual = match reference_heuristics(&fixture.labeled.tx, &fixture.labeled.prev_txs) {
+        Ok(actual) => actual,
+        Err(e) => return vec![e],
+    };
+
+    for (name, expected) in fixture.reference.iter() {
+        let Some(actual) = actual.get(name) else {
+            problems.push(format!("unknown reference heuristic: {}", name));
+            continue;
+        };
+        match (actual == expected, fixture.divergences.get(name)) {
+            (true, None) | (false, Some(_)) => {}
+            (false, None) => problems.push(format!(
+                "{}: reference {}, found {}",
+                name, expected, actual
+            )),
+            (true, Some(reason)) => problems.push(format!(
+                "{}: documented as diverging ({}) but agrees with the reference",
+                name, reason
+            )),
+        }
+    }
+    for name in REFERENCE_HEURISTICS {
+        if !fixture.reference.contains_key(name) {
+            problems.push(format!("missing reference heuristic: {}", name));
+        }
+    }
+    for name in fixture.divergences.keys() {
+        if !fixture.reference.contains_key(name) {
+            problems.push(format!("divergence without a reference value: {}", name));
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::load_fixtures;
+    use std::{
+        collections::BTreeSet,
+        path::{Path, PathBuf},
+    };
+
+    fn fixtures_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/wallets")
+    }
+
+    #[test]
+    fn test_conformance_with_reference() {
+        // Only fixtures with outputs of `fixtures/generate_reference.py` are checked
+        let fixtures = load_fixtures(&fixtures_dir())
+            .unwrap()
+            .into_iter()
+            .filter(|fixture| !fixture.reference.is_empty())
+            .collect::<Vec<_>>();
+
+        let failures = fixtures
+            .iter()
+            .flat_map(|fixture| {
+                check_conformance(fixture)
+                    .into_iter()
+                    .map(move |problem| format!("{}: {}", fixture.path.display(), problem))
+            })
+            .collect::<Vec<_>>();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_reference_heuristics_cover_all_names() {
+        let fixture = &load_fixtures(&fixtures_dir()).unwrap()[0];
+        let heuristics =
+            reference_heuristics(&fixture.labeled.tx, &fixture.labeled.prev_txs).unwrap();
+        assert!(heuristics.keys().eq(REFERENCE_HEURISTICS
+            .iter()
+            .copied()
+            .collect::<BTreeSet<_>>()));
+    }
+
+    #[test]
+    fn test_divergences() {
+        let mut fixture = load_fixtures(&fixtures_dir()).unwrap().remove(0);
+        fixture.reference =
+            reference_heuristics(&fixture.labeled.tx, &fixture.labeled.prev_txs).unwrap();
+        fixture.divergences.clear();
+        assert_eq!(check_conformance(&fixture), Vec::<String>::new());
+
+        fixture
+            .reference
+            .insert("version".to_string(), "3".to_string());
+        assert_eq!(
+            check_conformance(&fixture),
+            vec!["version: reference 3, found 2"]
+        );
+
+        fixture.reference.remove("low_r");
+        fixture.divergences = BTreeMap::from([
+            ("version".to_string(), "reason".to_string()),
+            ("signals_rbf".to_string(), "stale".to_string()),
+            ("low_r".to_string(), "missing".to_string()),
+        ]);
+        assert_eq!(
+            check_conformance(&fixture),
+            vec![
+                "signals_rbf: documented as diverging (stale) but agrees with the reference",
+                "missing reference heuristic: low_r",
+                "divergence without a reference value: low_r",
+            ]
+        );
+    }
+}
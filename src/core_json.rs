@@ -0,0 +1,200 @@
+//! Transactions in the JSON format of Bitcoin Core's `getrawtransaction` with verbosity 2,
+//! which embeds the output spent by each input, so no previous transactions are needed.
+
+use std::collections::HashSet;
+
+use bitcoin::{
+    consensus::encode::deserialize_hex, Amount, OutPoint, ScriptBuf, Transaction, TxOut,
+};
+use serde::Deserialize;
+
+use crate::{
+    change::ChangeHints, detect_wallet_from_prevouts, heuristics::Heuristics, prevouts::Prevout,
+    util::TxOutWithOutpoint, WalletType,
+};
+
+#[derive(Debug, Deserialize)]
+struct VerboseTransaction {
+    hex: String,
+    vin: Vec<VerboseInput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerboseInput {
+    txid: Option<String>,
+    vout: Option<u32>,
+    prevout: Option<VerbosePrevout>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerbosePrevout {
+    height: Option<u32>,
+    /// In BTC
+    value: f64,
+    #[serde(rename = "scriptPubKey")]
+    script_pubkey: VerboseScript,
+}
+
+#[derive(Debug, Deserialize)]
+struct VerboseScript {
+    hex: String,
+}
+
+/// A transaction together with the output spent by each of its inputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedTransaction {
+    pub tx: Transaction,
+    /// The output spent by each input, in input order
+    pub prevouts: Vec<Prevout>,
+}
+
+impl ResolvedTransaction {
+    fn prev_outs(&self) -> Vec<TxOutWithOutpoint> {
+        self.tx
+            .input
+            .iter()
+            .zip(self.prevouts.iter())
+            .map(|(txin, prevout)| TxOutWithOutpoint {
+                txout: prevout.txout.clone(),
+                outpoint: txin.previous_output,
+                height: prevout.height,
+            })
+            .collect()
+    }
+
+    pub fn heuristics(&self) -> Heuristics {
+        Heuristics::from_prevouts(&self.tx, &self.prev_outs(), &ChangeHints::default())
+    }
+
+    /// See [`crate::detect_wallet`]
+    pub fn detect_wallet(&self) -> (HashSet<WalletType>, Vec<String>) {
+        detect_wallet_from_prevouts(&self.tx, &self.prev_outs())
+    }
+}
+
+/// Parses the output of `getrawtransaction <txid> 2`.
+/// Coinbase transactions are rejected, since they spend no outputs.
+pub fn parse_verbose_transaction(json: &str) -> Result<ResolvedTransaction, String> {
+    let verbose: VerboseTransaction = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let tx: Transaction =
+        deserialize_hex(&verbose.hex).map_err(|e| format!("invalid transaction hex: {}", e))?;
+    if verbose.vin.len() != tx.input.len() {
+        return Err(format!(
+            "{} inputs listed, transaction has {}",
+            verbose.vin.len(),
+            tx.input.len()
+        ));
+    }
+
+    let prevouts = verbose
+        .vin
+        .into_iter()
+        .zip(tx.input.iter())
+        .enumerate()
+        .map(|(i, (input, txin))| {
+            let (Some(txid), Some(vout), Some(prevout)) = (input.txid, input.vout, input.prevout)
+            else {
+                return Err(format!("input {} has no prevout", i));
+            };
+            let txid = txid
+                .parse()
+                .map_err(|e| format!("input {}: invalid txid: {}", i, e))?;
+            if OutPoint::new(txid, vout) != txin.previous_output {
+                return Err(format!("input {} does not match the transaction", i));
+            }
+            let value = Amount::from_btc(prevout.value)
+                .map_err(|e| format!("input {}: invalid value: {}", i, e))?;
+            let script_pubkey = ScriptBuf::from_hex(&prevout.script_pubkey.hex)
+                .map_err(|e| format!("input {}: invalid scriptPubKey: {}", i, e))?;
+            Ok(Prevout {
+                txout: TxOut {
+                    value,
+                    script_pubkey,
+                },
+                height: prevout.height,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ResolvedTransaction { tx, prevouts })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{emulator::emulate, util::get_prevouts};
+    use bitcoin::consensus::encode::serialize_hex;
+    use serde_json::json;
+    use std::{fs, path::Path};
+
+    fn fixture(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/core")
+            .join(name);
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_verbose_fixtures() {
+        for (name, wallet) in [
+            ("electrum-regtest.json", WalletType::Electrum),
+            ("trezor-regtest.json", WalletType::Trezor),
+        ] {
+            let resolved = parse_verbose_transaction(&fixture(name)).unwrap();
+            assert!(resolved
+                .prevouts
+                .iter()
+                .all(|prevout| prevout.height.is_some()));
+            let (wallets, reasoning) = resolved.detect_wallet();
+            assert_eq!(
+                wallets,
+                HashSet::from([wallet]),
+                "{}: {:?}",
+                name,
+                reasoning
+            );
+            assert_eq!(resolved.heuristics().txid, resolved.tx.compute_txid());
+        }
+    }
+
+    #[test]
+    fn test_invalid_verbose_transaction() {
+        let json = fixture("electrum-regtest.json");
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value["vin"][0]["vout"] = 7.into();
+        assert_eq!(
+            parse_verbose_transaction(&value.to_string()).unwrap_err(),
+            "input 0 does not match the transaction"
+        );
+
+        value["vin"][0] = json!({ "coinbase": "03e8030000", "sequence": 4294967295u32 });
+        assert_eq!(
+            parse_verbose_transaction(&value.to_string()).unwrap_err(),
+            "input 0 has no prevout"
+        );
+    }
+
+    #[test]
+    fn test_prevouts_match_previous_transactions() {
+        let labeled = emulate(WalletType::BitcoinCore, 0).unwrap();
+        let prev_outs = get_prevouts(&labeled.tx, &labeled.prev_txs).unwrap();
+        let vin = prev_outs
+            .iter()
+            .map(|prev_out| {
+                json!({
+                    "txid": prev_out.outpoint.txid.to_string(),
+                    "vout": prev_out.outpoint.vout,
+                    "prevout": {
+                        "value": prev_out.txout.value.to_btc(),
+                        "scriptPubKey": { "hex": prev_out.txout.script_pubkey.to_hex_string() },
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        let verbose = json!({ "hex": serialize_hex(&labeled.tx), "vin": vin });
+
+        let resolved = parse_verbose_transaction(&verbose.to_string()).unwrap();
+        assert_eq!(resolved.tx, labeled.tx);
+        assert_eq!(resolved.prev_outs(), prev_outs);
+    }
+}
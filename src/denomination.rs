@@ -0,0 +1,164 @@
+use bitcoin::{Amount, Transaction};
+
+/// Amounts with at most this many significant digits are considered round, regardless of magnitude
+const MAX_ROUND_SIGNIFICANT_DIGITS: u32 = 2;
+/// Relative distance from a round fiat amount we still consider round,
+/// to allow for the exchange rate the wallet used differing from the supplied one
+const FIAT_ROUND_TOLERANCE: f64 = 0.005;
+
+/// The largest BTC denomination an amount is a whole multiple of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Enum))]
+pub enum Denomination {
+    /// Not a multiple of 100 sats
+    Sats,
+    /// Multiple of 100 sats (1 bit)
+    Bits,
+    /// Multiple of 0.001 BTC
+    MilliBtc,
+    /// Multiple of 0.01 BTC
+    CentiBtc,
+    /// Multiple of 0.1 BTC
+    DeciBtc,
+    /// Multiple of 1 BTC
+    Btc,
+}
+
+impl Denomination {
+    /// Largest denomination first
+    const ALL: [Denomination; 6] = [
+        Denomination::Btc,
+        Denomination::DeciBtc,
+        Denomination::CentiBtc,
+        Denomination::MilliBtc,
+        Denomination::Bits,
+        Denomination::Sats,
+    ];
+
+    fn to_sat(self) -> u64 {
+        match self {
+            Denomination::Btc => 100_000_000,
+            Denomination::DeciBtc => 10_000_000,
+            Denomination::CentiBtc => 1_000_000,
+            Denomination::MilliBtc => 100_000,
+            Denomination::Bits => 100,
+            Denomination::Sats => 1,
+        }
+    }
+}
+
+/// Roundness of an output amount
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct OutputAmount {
+    /// Number of trailing zeros of the amount in sats
+    pub trailing_zeros: u32,
+    /// Number of significant digits of the amount in sats
+    pub significant_digits: u32,
+    /// The largest BTC denomination the amount is a multiple of
+    pub denomination: Denomination,
+    /// Whether the amount is round in fiat, if an exchange rate was supplied
+    pub fiat_round: Option<bool>,
+}
+
+impl OutputAmount {
+    /// Returns true if the amount looks like it was chosen by a person rather than computed as change
+    pub fn is_round(&self) -> bool {
+        self.significant_digits <= MAX_ROUND_SIGNIFICANT_DIGITS || self.fiat_round == Some(true)
+    }
+}
+
+/// Returns true if the fiat value of the amount is close to a round fiat amount.
+/// Round fiat amounts are multiples of half the amount's order of magnitude, e.g. 50, 150 or 2500.
+fn is_fiat_round(amount: Amount, exchange_rate: f64) -> bool {
+    let fiat = amount.to_btc() * exchange_rate;
+    if !fiat.is_finite() || fiat <= 0.0 {
+        return false;
+    }
+
+    let step = 5.0 * 10f64.powi(fiat.log10().floor() as i32 - 1);
+    let nearest = (fiat / step).round() * step;
+    nearest > 0.0 && ((fiat - nearest).abs() / nearest) <= FIAT_ROUND_TOLERANCE
+}
+
+/// Analyzes the roundness of an amount.
+/// `exchange_rate` is the fiat price of one BTC, used for the optional fiat rounding check.
+pub(crate) fn analyze_amount(amount: Amount, exchange_rate: Option<f64>) -> OutputAmount {
+    let sats = amount.to_sat();
+    let (trailing_zeros, significant_digits) = if sats == 0 {
+        (0, 0)
+    } else {
+        let mut trailing_zeros = 0;
+        let mut remaining = sats;
+        while remaining.is_multiple_of(10) {
+            remaining /= 10;
+            trailing_zeros += 1;
+        }
+        (trailing_zeros, remaining.ilog10() + 1)
+    };
+    let denomination = Denomination::ALL
+        .into_iter()
+        .find(|denomination| sats.is_multiple_of(denomination.to_sat()))
+        .unwrap_or(Denomination::Sats);
+
+    OutputAmount {
+        trailing_zeros,
+        significant_digits,
+        denomination,
+        fiat_round: exchange_rate.map(|rate| is_fiat_round(amount, rate)),
+    }
+}
+
+/// Analyzes the roundness of every output amount
+pub(crate) fn get_output_amounts(
+    tx: &Transaction,
+    exchange_rate: Option<f64>,
+) -> Vec<OutputAmount> {
+    tx.output
+        .iter()
+        .map(|txout| analyze_amount(txout.value, exchange_rate))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_btc_denominations() {
+        let amount = analyze_amount(Amount::from_btc(0.01).unwrap(), None);
+        assert_eq!(amount.denomination, Denomination::CentiBtc);
+        assert_eq!(amount.trailing_zeros, 6);
+        assert_eq!(amount.significant_digits, 1);
+        assert!(amount.is_round());
+
+        let amount = analyze_amount(Amount::from_sat(250_000_000), None);
+        assert_eq!(amount.denomination, Denomination::DeciBtc);
+        assert!(amount.is_round());
+    }
+
+    #[test]
+    fn test_roundness_is_relative_to_magnitude() {
+        // Divisible by 100, but not something a person would type
+        let amount = analyze_amount(Amount::from_sat(1_234_500), None);
+        assert_eq!(amount.denomination, Denomination::Bits);
+        assert_eq!(amount.significant_digits, 5);
+        assert!(!amount.is_round());
+
+        let amount = analyze_amount(Amount::from_sat(4_321), None);
+        assert_eq!(amount.denomination, Denomination::Sats);
+        assert!(!amount.is_round());
+    }
+
+    #[test]
+    fn test_fiat_round() {
+        // 0.00123456 BTC at 40_500 per BTC is 50.0
+        let amount = analyze_amount(Amount::from_sat(123_456), Some(40_500.0));
+        assert_eq!(amount.fiat_round, Some(true));
+        assert!(amount.is_round());
+
+        let amount = analyze_amount(Amount::from_sat(123_456), Some(38_000.0));
+        assert_eq!(amount.fiat_round, Some(false));
+        assert!(!amount.is_round());
+    }
+}
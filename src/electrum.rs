@@ -0,0 +1,603 @@
+//! Looking up prevouts from an Electrum server such as electrs or Fulcrum.
+//!
+//! Spent outputs are found with `blockchain.transaction.get`. The height of the transaction
+//! creating an output comes from the history of the output's script, and is checked against
+//! the block header with the proof returned by `blockchain.transaction.get_merkle`.
+//! The height is unknown if the server refuses the history, e.g. because it is too large.
+//! Requests are pipelined, and outputs confirmed on the server are cached.
+//! Only plain TCP connections are supported.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Mutex,
+    time::Duration,
+};
+
+use bitcoin::{
+    block::Header,
+    consensus::encode::deserialize_hex,
+    hashes::{sha256, sha256d, Hash},
+    OutPoint, Script, Transaction, TxMerkleNode, TxOut, Txid,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::prevouts::{Prevout, PrevoutLookup};
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+const PROTOCOL_VERSION: &str = "1.4";
+
+#[derive(Debug, Deserialize)]
+struct HistoryItem {
+    tx_hash: String,
+    /// 0 or -1 for mempool transactions
+    height: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct MerkleProof {
+    block_height: u32,
+    merkle: Vec<String>,
+    pos: usize,
+}
+
+/// The Electrum protocol identifies scripts by their reversed SHA256 hash
+fn script_hash(script: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    hex::encode(hash)
+}
+
+/// Computes the merkle root from a transaction and its merkle branch
+fn merkle_root(txid: &Txid, proof: &MerkleProof) -> Result<TxMerkleNode, String> {
+    let mut node = txid.to_raw_hash();
+    for (level, sibling) in proof.merkle.iter().enumerate() {
+        let sibling = sibling
+            .parse::<TxMerkleNode>()
+            .map_err(|e| format!("invalid merkle branch: {}", e))?
+            .to_raw_hash();
+        let (left, right) = match (proof.pos >> level) & 1 {
+            0 => (node, sibling),
+            _ => (sibling, node),
+        };
+        node = sha256d::Hash::hash(&[left.to_byte_array(), right.to_byte_array()].concat());
+    }
+    Ok(TxMerkleNode::from_raw_hash(node))
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: u64,
+}
+
+/// A transaction as known to the server
+struct ServerTransaction {
+    outputs: Vec<TxOut>,
+    height: Option<u32>,
+}
+
+/// A prevout lookup backed by an Electrum server
+pub struct ElectrumClient {
+    connection: Mutex<Connection>,
+    /// Outputs and heights of confirmed transactions, which do not change
+    confirmed: Mutex<HashMap<Txid, (Vec<TxOut>, u32)>>,
+}
+
+impl ElectrumClient {
+    /// Connects to the server at `addr`, e.g. `127.0.0.1:50001`
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self, String> {
+        let writer = TcpStream::connect(addr).map_err(|e| e.to_string())?;
+        writer
+            .set_read_timeout(Some(TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        let reader = BufReader::new(writer.try_clone().map_err(|e| e.to_string())?);
+        let client = Self {
+            connection: Mutex::new(Connection {
+                reader,
+                writer,
+                next_id: 0,
+            }),
+            confirmed: Mutex::new(HashMap::new()),
+        };
+        client
+            .call_all(vec![(
+                "server.version",
+                json!(["wallet-fingerprint", PROTOCOL_VERSION]),
+            )])?
+            .remove(0)
+            .map_err(|e| format!("server.version: {}", e))?;
+        Ok(client)
+    }
+
+    /// Sends the calls without waiting for responses in between,
+    /// returning the result or error message of each call in order
+    fn call_all(&self, calls: Vec<(&str, Value)>) -> Result<Vec<Result<Value, String>>, String> {
+        let mut connection = self.connection.lock().unwrap();
+        let first_id = connection.next_id;
+        connection.next_id += calls.len() as u64;
+
+        let mut request = String::new();
+        for (id, (method, params)) in (first_id..).zip(calls.iter()) {
+            let call = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+            request.push_str(&call.to_string());
+            request.push('\n');
+        }
+        connection
+            .writer
+            .write_all(request.as_bytes())
+            .map_err(|e| e.to_string())?;
+
+        let mut outcomes = calls.iter().map(|_| None).collect::<Vec<_>>();
+        let mut pending = calls.len();
+        while pending > 0 {
+            let mut line = String::new();
+            if connection
+                .reader
+                .read_line(&mut line)
+                .map_err(|e| e.to_string())?
+                == 0
+            {
+                return Err("connection closed by the server".to_string());
+            }
+            let response: Value = serde_json::from_str(&line).map_err(|e| e.to_string())?;
+            // Skip notifications, which have no id
+            let Some(slot) = response["id"]
+                .as_u64()
+                .and_then(|id| id.checked_sub(first_id))
+                .and_then(|i| outcomes.get_mut(i as usize))
+            else {
+                continue;
+            };
+            let outcome = match response.get("error").filter(|error| !error.is_null()) {
+                Some(error) => Err(error["message"].as_str().unwrap_or("error").to_string()),
+                None => Ok(response["result"].clone()),
+            };
+            if slot.replace(outcome).is_none() {
+                pending -= 1;
+            }
+        }
+        Ok(outcomes.into_iter().flatten().collect())
+    }
+
+    /// Fetches the transactions and their heights, leaving out those unknown to the server
+    fn transactions(&self, txids: &[Txid]) -> Result<HashMap<Txid, ServerTransaction>, String> {
+        let outcomes = self.call_all(
+            txids
+                .iter()
+                .map(|txid| ("blockchain.transaction.get", json!([txid.to_string()])))
+                .collect(),
+        )?;
+        let mut txs = Vec::new();
+        for (txid, outcome) in txids.iter().zip(outcomes) {
+            // Servers report unknown transactions as errors without a standard code
+            let Ok(result) = outcome else {
+                continue;
+            };
+            let hex = result
+                .as_str()
+                .ok_or_else(|| format!("{}: invalid transaction", txid))?;
+            let tx: Transaction = deserialize_hex(hex)
+                .map_err(|e| format!("{}: invalid transaction: {}", txid, e))?;
+            if tx.compute_txid() != *txid {
+                return Err(format!("{}: server returned another transaction", txid));
+            }
+            txs.push(tx);
+        }
+
+        // The history of any script of the transaction includes it with its height.
+        // Unspendable OP_RETURN scripts may not be indexed, and a transaction with only those
+        // has no outputs to look up.
+        let scripts = txs
+            .iter()
+            .map(|tx| {
+                tx.output
+                    .iter()
+                    .find(|txout| !txout.script_pubkey.is_op_return())
+                    .map(|txout| script_hash(&txout.script_pubkey))
+            })
+            .collect::<Vec<_>>();
+        let txs = txs
+            .into_iter()
+            .zip(scripts)
+            .filter_map(|(tx, script)| Some((tx, script?)))
+            .collect::<Vec<_>>();
+        let outcomes = self.call_all(
+            txs.iter()
+                .map(|(_, script)| ("blockchain.scripthash.get_history", json!([script])))
+                .collect(),
+        )?;
+        let txs = txs.into_iter().map(|(tx, _)| tx).collect::<Vec<_>>();
+        let mut heights = Vec::new();
+        for (tx, outcome) in txs.iter().zip(outcomes) {
+            let txid = tx.compute_txid();
+            // Servers refuse histories that are too large, leaving the height unknown
+            let Ok(result) = outcome else {
+                heights.push(None);
+                continue;
+            };
+            let history: Vec<HistoryItem> =
+                serde_json::from_value(result).map_err(|e| format!("{}: history: {}", txid, e))?;
+            let height = history
+                .iter()
+                .find(|item| item.tx_hash == txid.to_string())
+                .and_then(|item| u32::try_from(item.height).ok())
+                .filter(|height| *height > 0);
+            heights.push(height);
+        }
+
+        self.verify_heights(&txs, &heights)?;
+        Ok(txs
+            .into_iter()
+            .zip(heights)
+            .map(|(tx, height)| {
+                let txid = tx.compute_txid();
+                let server_tx = ServerTransaction {
+                    outputs: tx.output,
+                    height,
+                };
+                (txid, server_tx)
+            })
+            .collect())
+    }
+
+    /// Checks that the confirmed transactions are included in the blocks at their heights
+    fn verify_heights(&self, txs: &[Transaction], heights: &[Option<u32>]) -> Result<(), String> {
+        let confirmed = txs
+            .iter()
+            .zip(heights)
+            .filter_map(|(tx, height)| Some((tx.compute_txid(), (*height)?)))
+            .collect::<Vec<_>>();
+        let calls = confirmed
+            .iter()
+            .flat_map(|(txid, height)| {
+                [
+                    (
+                        "blockchain.transaction.get_merkle",
+                        json!([txid.to_string(), height]),
+                    ),
+                    ("blockchain.block.header", json!([height])),
+                ]
+            })
+            .collect();
+        let mut outcomes = self.call_all(calls)?.into_iter();
+
+        for (txid, height) in confirmed {
+            let (Some(proof), Some(header)) = (outcomes.next(), outcomes.next()) else {
+                return Err("missing responses".to_string());
+            };
+            let proof: MerkleProof = proof
+                .and_then(|result| serde_json::from_value(result).map_err(|e| e.to_string()))
+                .map_err(|e| format!("{}: merkle proof: {}", txid, e))?;
+            let header: Header = header
+                .and_then(|result| {
+                    let hex = result.as_str().ok_or("invalid header")?;
+                    deserialize_hex(hex).map_err(|e| e.to_string())
+                })
+                .map_err(|e| format!("{}: header {}: {}", txid, height, e))?;
+            if proof.block_height != height || merkle_root(&txid, &proof)? != header.merkle_root {
+                return Err(format!(
+                    "{}: invalid merkle proof at height {}",
+                    txid, height
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PrevoutLookup for ElectrumClient {
+    fn prevout(&self, outpoint: &OutPoint) -> Result<Option<Prevout>, String> {
+        Ok(self.prevouts(&[*outpoint])?.into_iter().next().flatten())
+    }
+
+    /// Takes a few round trips regardless of the number of outputs
+    fn prevouts(&self, outpoints: &[OutPoint]) -> Result<Vec<Option<Prevout>>, String> {
+        let mut found = {
+            let confirmed = self.confirmed.lock().unwrap();
+            outpoints
+                .iter()
+                .filter_map(|outpoint| {
+                    let (outputs, height) = confirmed.get(&outpoint.txid)?;
+                    let server_tx = ServerTransaction {
+                        outputs: outputs.clone(),
+                        height: Some(*height),
+                    };
+                    Some((outpoint.txid, server_tx))
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        let mut missing = outpoints
+            .iter()
+            .map(|outpoint| outpoint.txid)
+            .filter(|txid| !found.contains_key(txid))
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing.dedup();
+
+        if !missing.is_empty() {
+            let fetched = self.transactions(&missing)?;
+            let mut confirmed = self.confirmed.lock().unwrap();
+            for (txid, server_tx) in fetched {
+                if let Some(height) = server_tx.height {
+                    confirmed.insert(txid, (server_tx.outputs.clone(), height));
+                }
+                found.insert(txid, server_tx);
+            }
+        }
+
+        Ok(outpoints
+            .iter()
+            .map(|outpoint| {
+                let server_tx = found.get(&outpoint.txid)?;
+                Some(Prevout {
+                    txout: server_tx.outputs.get(outpoint.vout as usize)?.clone(),
+                    height: server_tx.height,
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        heuristics::Heuristics,
+        test_utils::{ScriptType, TxBuilder},
+    };
+    use bitcoin::{
+        absolute::LockTime, block::Version as BlockVersion, consensus::encode::serialize_hex,
+        transaction::Version, Amount, Block, BlockHash, CompactTarget, TxIn,
+    };
+    use std::{
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    /// The state of the server: transactions with their history heights, blocks by height,
+    /// and the script hashes whose history is too large to serve
+    #[derive(Clone)]
+    struct Server {
+        txs: HashMap<Txid, (Transaction, i64)>,
+        blocks: HashMap<u32, Block>,
+        too_large: Vec<String>,
+    }
+
+    impl Server {
+        fn call(&self, method: &str, params: &[Value]) -> Result<Value, &'static str> {
+            let txid = || params[0].as_str().unwrap().parse::<Txid>().unwrap();
+            match method {
+                "server.version" => Ok(json!(["stand-in", PROTOCOL_VERSION])),
+                "blockchain.transaction.get" => match self.txs.get(&txid()) {
+                    Some((tx, _)) => Ok(json!(serialize_hex(tx))),
+                    None => Err("missing transaction"),
+                },
+                "blockchain.scripthash.get_history" => {
+                    let script_hash = params[0].as_str().unwrap();
+                    if self.too_large.iter().any(|large| large == script_hash) {
+                        return Err("history too large");
+                    }
+                    let history = self
+                        .txs
+                        .values()
+                        .filter(|(tx, _)| {
+                            tx.output
+                                .iter()
+                                .any(|txout| script_hash == super::script_hash(&txout.script_pubkey))
+                        })
+                        .map(|(tx, height)| {
+                            json!({ "tx_hash": tx.compute_txid().to_string(), "height": height })
+                        })
+                        .collect::<Vec<_>>();
+                    Ok(json!(history))
+                }
+                "blockchain.transaction.get_merkle" => {
+                    let height = params[1].as_u64().unwrap() as u32;
+                    let txids = self.blocks[&height]
+                        .txdata
+                        .iter()
+                        .map(|tx| tx.compute_txid().to_raw_hash())
+                        .collect::<Vec<_>>();
+                    let pos = txids
+                        .iter()
+                        .position(|hash| *hash == txid().to_raw_hash())
+                        .unwrap();
+                    let merkle = merkle_branch(txids, pos)
+                        .iter()
+                        .map(|hash| TxMerkleNode::from_raw_hash(*hash).to_string())
+                        .collect::<Vec<_>>();
+                    Ok(json!({ "block_height": height, "merkle": merkle, "pos": pos }))
+                }
+                "blockchain.block.header" => {
+                    let height = params[0].as_u64().unwrap() as u32;
+                    Ok(json!(serialize_hex(&self.blocks[&height].header)))
+                }
+                _ => Err("unknown method"),
+            }
+        }
+    }
+
+    fn merkle_branch(mut level: Vec<sha256d::Hash>, mut pos: usize) -> Vec<sha256d::Hash> {
+        let mut branch = Vec::new();
+        while level.len() > 1 {
+            if level.len() % 2 == 1 {
+                level.push(*level.last().unwrap());
+            }
+            branch.push(level[pos ^ 1]);
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    sha256d::Hash::hash(
+                        &[pair[0].to_byte_array(), pair[1].to_byte_array()].concat(),
+                    )
+                })
+                .collect();
+            pos /= 2;
+        }
+        branch
+    }
+
+    /// Serves the state on a local port until the test exits, counting the requests
+    fn serve(server: Server) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (server, counter) = (server.clone(), counter.clone());
+                let mut writer = stream.unwrap();
+                let reader = BufReader::new(writer.try_clone().unwrap());
+                thread::spawn(move || {
+                    for line in reader.lines() {
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+                        let params = request["params"].as_array().unwrap();
+                        let mut response =
+                            match server.call(request["method"].as_str().unwrap(), params) {
+                                Ok(result) => json!({ "jsonrpc": "2.0", "result": result }),
+                                Err(message) => json!({
+                                    "jsonrpc": "2.0",
+                                    "error": { "code": 1, "message": message },
+                                }),
+                            };
+                        response["id"] = request["id"].clone();
+                        writeln!(writer, "{}", response).unwrap();
+                    }
+                });
+            }
+        });
+        (addr, requests)
+    }
+
+    fn block(txdata: Vec<Transaction>) -> Block {
+        let mut block = Block {
+            header: Header {
+                version: BlockVersion::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
+
+    fn coinbase(height: u8) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                script_sig: vec![1, height].into(),
+                ..Default::default()
+            }],
+            output: vec![TxOut::NULL],
+        }
+    }
+
+    fn spend() -> (Transaction, Vec<Transaction>, Server) {
+        let (tx, prev_txs) = TxBuilder::new(0)
+            .input(Amount::from_sat(100_000), ScriptType::P2wpkh)
+            .input(Amount::from_sat(50_000), ScriptType::P2tr)
+            .input(Amount::from_sat(20_000), ScriptType::P2pkh)
+            .output(Amount::from_sat(120_000), ScriptType::P2wpkh)
+            .change(Amount::from_sat(49_000), ScriptType::P2wpkh)
+            .build();
+        // The first two parents are confirmed in the same block, the last one is in the mempool
+        let server = Server {
+            txs: HashMap::from([
+                (prev_txs[0].compute_txid(), (prev_txs[0].clone(), 150)),
+                (prev_txs[1].compute_txid(), (prev_txs[1].clone(), 150)),
+                (prev_txs[2].compute_txid(), (prev_txs[2].clone(), 0)),
+            ]),
+            blocks: HashMap::from([(
+                150,
+                block(vec![
+                    coinbase(150),
+                    prev_txs[0].clone(),
+                    prev_txs[1].clone(),
+                ]),
+            )]),
+            too_large: vec![],
+        };
+        (tx, prev_txs, server)
+    }
+
+    #[test]
+    fn test_electrum_client() {
+        let (tx, prev_txs, server) = spend();
+        let (addr, requests) = serve(server);
+        let client = ElectrumClient::connect(addr.as_str()).unwrap();
+
+        let heuristics = Heuristics::from_lookup(&tx, &client).unwrap().unwrap();
+        assert_eq!(heuristics.txid, tx.compute_txid());
+        // The handshake, 3 transactions, 3 histories and 2 proofs with their headers
+        assert_eq!(requests.load(Ordering::SeqCst), 1 + 3 + 3 + 2 * 2);
+
+        let outpoints = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        let prevouts = client.prevouts(&outpoints).unwrap();
+        let heights = prevouts
+            .iter()
+            .map(|prevout| prevout.as_ref().unwrap().height)
+            .collect::<Vec<_>>();
+        assert_eq!(heights, vec![Some(150), Some(150), None]);
+        for (prevout, prev_tx) in prevouts.iter().zip(prev_txs.iter()) {
+            assert_eq!(prevout.as_ref().unwrap().txout, prev_tx.output[0]);
+        }
+        // Only the unconfirmed transaction is fetched again
+        assert_eq!(requests.load(Ordering::SeqCst), 11 + 2);
+
+        let unknown = OutPoint::new(Txid::all_zeros(), 0);
+        assert_eq!(client.prevout(&unknown), Ok(None));
+    }
+
+    #[test]
+    fn test_invalid_merkle_proof() {
+        let (tx, _, mut server) = spend();
+        let block = server.blocks.get_mut(&150).unwrap();
+        block.header.merkle_root = TxMerkleNode::all_zeros();
+        let (addr, _) = serve(server);
+        let client = ElectrumClient::connect(addr.as_str()).unwrap();
+
+        assert!(client
+            .prevout(&tx.input[0].previous_output)
+            .unwrap_err()
+            .ends_with("invalid merkle proof at height 150"));
+    }
+
+    #[test]
+    fn test_history_too_large() {
+        let (tx, prev_txs, mut server) = spend();
+        server
+            .too_large
+            .push(script_hash(&prev_txs[0].output[0].script_pubkey));
+        let (addr, _) = serve(server);
+        let client = ElectrumClient::connect(addr.as_str()).unwrap();
+
+        let outpoints = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        let prevouts = client.prevouts(&outpoints).unwrap();
+        let heights = prevouts
+            .iter()
+            .map(|prevout| prevout.as_ref().unwrap().height)
+            .collect::<Vec<_>>();
+        assert_eq!(heights, vec![None, Some(150), None]);
+    }
+}
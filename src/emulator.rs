@@ -0,0 +1,191 @@
+//! Emulators that build regtest transactions following the known policies of each wallet.
+//! Running detection over their output checks that the wallet profiles are consistent
+//! with the policies they were derived from.
+
+use bitcoin::{absolute::LockTime, transaction::Version, Amount, Sequence};
+
+use crate::{
+    rules::LabeledTransaction,
+    test_utils::{ChangePosition, ScriptType, TxBuilder, TxOrdering},
+    WalletType,
+};
+
+/// Wallets we know the policies of
+pub const EMULATED_WALLETS: [WalletType; 8] = [
+    WalletType::BitcoinCore,
+    WalletType::Electrum,
+    WalletType::BlueWallet,
+    WalletType::Coinbase,
+    WalletType::Exodus,
+    WalletType::Trust,
+    WalletType::Trezor,
+    WalletType::Ledger,
+];
+
+/// Builds and signs a transaction the way `wallet` would.
+/// Amounts, keys and change position vary with `seed`.
+/// Returns None for wallet types that do not stand for a single wallet.
+pub fn emulate(wallet: WalletType, seed: u64) -> Option<LabeledTransaction> {
+    // Payments are round, change is whatever is left after the fee
+    let payment = Amount::from_sat(10_000 * (seed % 9 + 1));
+    let fee = Amount::from_sat(1_000 + 37 * (seed % 11));
+    let funding = Amount::from_sat(150_000 + 1_733 * (seed % 13));
+    let change = funding - payment - fee;
+    let tip_height = 800_000 + (seed % 1_000) as u32;
+    let anti_fee_snipe = LockTime::from_height(tip_height).expect("Valid height");
+
+    let builder = TxBuilder::new(seed);
+    let builder = match wallet {
+        // Anti fee sniping, RBF, low R, change type matches the payment, random change position
+        WalletType::BitcoinCore => builder
+            .lock_time(anti_fee_snipe)
+            .sequence(Sequence::ENABLE_RBF_NO_LOCKTIME)
+            .input(funding, ScriptType::P2wpkh)
+            .output(payment, ScriptType::P2tr)
+            .change(change, ScriptType::P2tr)
+            .change_position(if seed.is_multiple_of(2) {
+                ChangePosition::First
+            } else {
+                ChangePosition::Last
+            }),
+        // Anti fee sniping, RBF, low R, BIP 69, change type matches the inputs
+        WalletType::Electrum => builder
+            .lock_time(anti_fee_snipe)
+            .sequence(Sequence::ENABLE_RBF_NO_LOCKTIME)
+            .input(funding, ScriptType::P2wpkh)
+            .output(payment, ScriptType::P2pkh)
+            .change(change, ScriptType::P2wpkh)
+            .input_ordering(TxOrdering::Bip69)
+            .output_ordering(TxOrdering::Bip69),
+        // RBF without anti fee sniping, change last
+        WalletType::BlueWallet => builder
+            .sequence(Sequence(0x8000_0000))
+            .input(funding, ScriptType::P2wpkh)
+            .output(payment, ScriptType::P2wpkh)
+            .change(change, ScriptType::P2wpkh),
+        // No RBF, change last to a fresh address
+        WalletType::Coinbase => builder
+            .input(funding, ScriptType::P2wpkh)
+            .output(payment, ScriptType::P2wpkh)
+            .change(change, ScriptType::P2wpkh),
+        // No RBF, change back to the input address
+        WalletType::Exodus => builder
+            .input(funding, ScriptType::P2wpkh)
+            .output(payment, ScriptType::P2wpkh)
+            .change(change, ScriptType::P2wpkh)
+            .change_reuses_input_address(true),
+        // Version 1, RBF, change back to the input address
+        WalletType::Trust => builder
+            .version(Version::ONE)
+            .sequence(Sequence::ENABLE_RBF_NO_LOCKTIME)
+            .input(funding, ScriptType::P2wpkh)
+            .output(payment, ScriptType::P2wpkh)
+            .change(change, ScriptType::P2wpkh)
+            .change_reuses_input_address(true),
+        // Version 1, RBF, BIP 69
+        WalletType::Trezor => builder
+            .version(Version::ONE)
+            .sequence(Sequence::ENABLE_RBF_NO_LOCKTIME)
+            .input(funding, ScriptType::P2wpkh)
+            .input(funding, ScriptType::P2wpkh)
+            .output(payment, ScriptType::P2pkh)
+            .output(payment, ScriptType::P2wpkh)
+            .change(change + funding - payment, ScriptType::P2wpkh)
+            .input_ordering(TxOrdering::Bip69)
+            .output_ordering(TxOrdering::Bip69),
+        // Version 1, zero sequences, change last even when it is the smaller output
+        WalletType::Ledger => builder
+            .version(Version::ONE)
+            .sequence(Sequence::ZERO)
+            .input(funding, ScriptType::P2wpkh)
+            .output(Amount::from_sat(100_000), ScriptType::P2pkh)
+            .change(
+                funding - Amount::from_sat(100_000) - fee,
+                ScriptType::P2wpkh,
+            ),
+        WalletType::Unclear | WalletType::Other => return None,
+    };
+
+    let (tx, prev_txs) = builder.build();
+    Some(LabeledTransaction {
+        tx,
+        prev_txs,
+        wallet,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        change::ChangeHints,
+        detect_wallet,
+        evaluation::evaluate,
+        heuristics::Heuristics,
+        rules::{learn_ruleset, TrainingConfig},
+        util::get_prevouts,
+    };
+    use std::collections::HashSet;
+
+    fn corpus(seeds: std::ops::Range<u64>) -> Vec<LabeledTransaction> {
+        EMULATED_WALLETS
+            .into_iter()
+            .flat_map(|wallet| seeds.clone().filter_map(move |seed| emulate(wallet, seed)))
+            .collect()
+    }
+
+    #[test]
+    fn test_emulators_are_detected() {
+        for wallet in EMULATED_WALLETS {
+            for seed in 0..20 {
+                let labeled = emulate(wallet, seed).unwrap();
+                let (wallets, reasoning) = detect_wallet(&labeled.tx, &labeled.prev_txs).unwrap();
+                assert_eq!(
+                    wallets,
+                    HashSet::from([wallet]),
+                    "seed {}: {:?}",
+                    seed,
+                    reasoning
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_taproot_core_spend_is_detected() {
+        // Without ECDSA signatures there is no R value to grind
+        let (tx, prev_txs) = TxBuilder::new(0)
+            .lock_time(LockTime::from_height(800_000).unwrap())
+            .sequence(Sequence::ENABLE_RBF_NO_LOCKTIME)
+            .input(Amount::from_sat(150_000), ScriptType::P2tr)
+            .output(Amount::from_sat(50_000), ScriptType::P2tr)
+            .change(Amount::from_sat(99_000), ScriptType::P2tr)
+            .build();
+        let (wallets, reasoning) = detect_wallet(&tx, &prev_txs).unwrap();
+        assert!(
+            wallets.contains(&WalletType::BitcoinCore),
+            "{:?}",
+            reasoning
+        );
+        assert!(reasoning.contains(&"Low r signatures only".to_string()));
+    }
+
+    #[test]
+    fn test_evaluate_emulators() {
+        let evaluation = evaluate(&corpus(0..5)).unwrap();
+        assert_eq!(evaluation.total, 40);
+        assert_eq!(evaluation.accuracy(), 1.0, "{}", evaluation);
+        assert!(emulate(WalletType::Other, 0).is_none());
+    }
+
+    #[test]
+    fn test_learned_ruleset_generalizes() {
+        let ruleset = learn_ruleset(&corpus(0..10), &TrainingConfig::default()).unwrap();
+        for labeled in corpus(100..105) {
+            let prev_outs = get_prevouts(&labeled.tx, &labeled.prev_txs).unwrap();
+            let heuristics =
+                Heuristics::from_prevouts(&labeled.tx, &prev_outs, &ChangeHints::default());
+            assert_eq!(ruleset.classify(&heuristics), Some(labeled.wallet));
+        }
+    }
+}
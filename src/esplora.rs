@@ -0,0 +1,446 @@
+//! Transactions in the JSON format of Esplora based APIs such as mempool.space, which embeds
+//! the output spent by each input and the confirmation status of the transaction.
+//! With the `esplora` feature, `EsploraClient` fetches transactions from such an API.
+
+use bitcoin::{
+    absolute::LockTime, transaction::Version, Amount, OutPoint, ScriptBuf, Sequence, Transaction,
+    TxIn, TxOut, Witness,
+};
+use serde::Deserialize;
+
+use crate::{core_json::ResolvedTransaction, prevouts::Prevout};
+
+#[derive(Debug, Deserialize)]
+struct EsploraTx {
+    txid: String,
+    version: i32,
+    locktime: u32,
+    vin: Vec<EsploraInput>,
+    vout: Vec<EsploraOutput>,
+    status: EsploraStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraInput {
+    txid: String,
+    vout: u32,
+    prevout: Option<EsploraOutput>,
+    scriptsig: String,
+    /// Omitted for inputs without a witness
+    #[serde(default)]
+    witness: Vec<String>,
+    sequence: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraOutput {
+    scriptpubkey: String,
+    /// In satoshis
+    value: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraStatus {
+    /// Only present if confirmed
+    block_height: Option<u32>,
+}
+
+impl EsploraOutput {
+    fn txout(&self) -> Result<TxOut, String> {
+        Ok(TxOut {
+            value: Amount::from_sat(self.value),
+            script_pubkey: ScriptBuf::from_hex(&self.scriptpubkey)
+                .map_err(|e| format!("invalid scriptpubkey: {}", e))?,
+        })
+    }
+}
+
+impl EsploraTx {
+    /// Rebuilds the transaction from its fields, checking it against the listed txid
+    fn transaction(&self) -> Result<Transaction, String> {
+        let input = self
+            .vin
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let txid = input
+                    .txid
+                    .parse()
+                    .map_err(|e| format!("input {}: invalid txid: {}", i, e))?;
+                let script_sig = ScriptBuf::from_hex(&input.scriptsig)
+                    .map_err(|e| format!("input {}: invalid scriptsig: {}", i, e))?;
+                let witness = input
+                    .witness
+                    .iter()
+                    .map(hex::decode)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| format!("input {}: invalid witness: {}", i, e))?;
+                Ok(TxIn {
+                    previous_output: OutPoint::new(txid, input.vout),
+                    script_sig,
+                    sequence: Sequence(input.sequence),
+                    witness: Witness::from_slice(&witness),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let output = self
+            .vout
+            .iter()
+            .enumerate()
+            .map(|(i, output)| output.txout().map_err(|e| format!("output {}: {}", i, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let tx = Transaction {
+            version: Version(self.version),
+            lock_time: LockTime::from_consensus(self.locktime),
+            input,
+            output,
+        };
+        if tx.compute_txid().to_string() != self.txid {
+            return Err(format!("transaction does not match txid {}", self.txid));
+        }
+        Ok(tx)
+    }
+
+    fn resolve(&self) -> Result<EsploraTransaction, String> {
+        let tx = self.transaction()?;
+        let prevouts = self
+            .vin
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let prevout = input
+                    .prevout
+                    .as_ref()
+                    .ok_or_else(|| format!("input {} has no prevout", i))?;
+                Ok(Prevout {
+                    txout: prevout.txout().map_err(|e| format!("input {}: {}", i, e))?,
+                    height: None,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(EsploraTransaction {
+            resolved: ResolvedTransaction { tx, prevouts },
+            height: self.status.block_height,
+        })
+    }
+}
+
+/// A transaction loaded from an Esplora based API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EsploraTransaction {
+    /// The transaction and the outputs it spends.
+    /// The JSON does not include the heights of the spent outputs, so they are unknown.
+    pub resolved: ResolvedTransaction,
+    /// Height of the block confirming the transaction, None if unconfirmed
+    pub height: Option<u32>,
+}
+
+/// Parses the output of `GET /tx/:txid`.
+/// Coinbase transactions are rejected, since they spend no outputs.
+pub fn parse_esplora_transaction(json: &str) -> Result<EsploraTransaction, String> {
+    let esplora: EsploraTx = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    esplora.resolve()
+}
+
+#[cfg(feature = "esplora")]
+pub use client::EsploraClient;
+
+#[cfg(feature = "esplora")]
+mod client {
+    use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+    use bitcoin::{OutPoint, TxOut, Txid};
+
+    use super::{EsploraTransaction, EsploraTx};
+    use crate::prevouts::{Prevout, PrevoutLookup};
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// The outputs of a transaction and its confirmation height
+    type Outputs = (Vec<TxOut>, Option<u32>);
+
+    /// A blocking client for an Esplora based API
+    pub struct EsploraClient {
+        base_url: String,
+        agent: ureq::Agent,
+        /// Outputs and heights of confirmed transactions, which do not change
+        confirmed: Mutex<HashMap<Txid, (Vec<TxOut>, u32)>>,
+    }
+
+    impl EsploraClient {
+        /// `base_url` is the root of the API, e.g. `https://mempool.space/api`
+        pub fn new(base_url: &str) -> Self {
+            Self {
+                base_url: base_url.trim_end_matches('/').to_string(),
+                agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+                confirmed: Mutex::new(HashMap::new()),
+            }
+        }
+
+        /// Returns None if the API does not know the transaction
+        fn get_tx(&self, txid: &Txid) -> Result<Option<EsploraTx>, String> {
+            let url = format!("{}/tx/{}", self.base_url, txid);
+            let response = match self.agent.get(&url).call() {
+                Ok(response) => response,
+                Err(ureq::Error::Status(404, _)) => return Ok(None),
+                Err(e) => return Err(format!("{}: {}", url, e)),
+            };
+            let body = response
+                .into_string()
+                .map_err(|e| format!("{}: {}", url, e))?;
+            serde_json::from_str(&body)
+                .map(Some)
+                .map_err(|e| format!("{}: {}", url, e))
+        }
+
+        fn outputs(&self, txid: &Txid) -> Result<Option<Outputs>, String> {
+            if let Some((outputs, height)) = self.confirmed.lock().unwrap().get(txid) {
+                return Ok(Some((outputs.clone(), Some(*height))));
+            }
+            let Some(esplora) = self.get_tx(txid)? else {
+                return Ok(None);
+            };
+            let tx = esplora.transaction()?;
+            let height = esplora.status.block_height;
+            if let Some(height) = height {
+                self.confirmed
+                    .lock()
+                    .unwrap()
+                    .insert(*txid, (tx.output.clone(), height));
+            }
+            Ok(Some((tx.output, height)))
+        }
+
+        /// Fetches a transaction and the heights of the outputs it spends,
+        /// which takes a request per unconfirmed or not yet fetched parent transaction
+        pub fn transaction(&self, txid: &Txid) -> Result<EsploraTransaction, String> {
+            let esplora = self
+                .get_tx(txid)?
+                .ok_or_else(|| format!("{}: transaction not found", txid))?;
+            let mut transaction = esplora.resolve()?;
+            let resolved = &mut transaction.resolved;
+            for (prevout, txin) in resolved.prevouts.iter_mut().zip(resolved.tx.input.iter()) {
+                prevout.height = self
+                    .outputs(&txin.previous_output.txid)?
+                    .and_then(|(_, height)| height);
+            }
+            Ok(transaction)
+        }
+    }
+
+    impl PrevoutLookup for EsploraClient {
+        fn prevout(&self, outpoint: &OutPoint) -> Result<Option<Prevout>, String> {
+            Ok(self.outputs(&outpoint.txid)?.and_then(|(outputs, height)| {
+                outputs
+                    .into_iter()
+                    .nth(outpoint.vout as usize)
+                    .map(|txout| Prevout { txout, height })
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core_json::parse_verbose_transaction, WalletType};
+    use std::{collections::HashSet, fs, path::Path};
+
+    fn fixture(path: &str) -> String {
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap()
+    }
+
+    #[test]
+    fn test_esplora_fixture() {
+        let esplora =
+            parse_esplora_transaction(&fixture("fixtures/esplora/trezor-regtest.json")).unwrap();
+        assert_eq!(esplora.height, Some(161));
+        let (wallets, reasoning) = esplora.resolved.detect_wallet();
+        assert_eq!(
+            wallets,
+            HashSet::from([WalletType::Trezor]),
+            "{:?}",
+            reasoning
+        );
+
+        // The same transaction as returned by Bitcoin Core
+        let core =
+            parse_verbose_transaction(&fixture("fixtures/core/trezor-regtest.json")).unwrap();
+        assert_eq!(esplora.resolved.tx, core.tx);
+        for (prevout, core_prevout) in esplora.resolved.prevouts.iter().zip(core.prevouts) {
+            assert_eq!(prevout.txout, core_prevout.txout);
+            assert_eq!(prevout.height, None);
+        }
+    }
+
+    #[test]
+    fn test_invalid_esplora_transaction() {
+        let json = fixture("fixtures/esplora/trezor-regtest.json");
+        let mut value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value["vin"][0]["prevout"] = serde_json::Value::Null;
+        assert_eq!(
+            parse_esplora_transaction(&value.to_string()).unwrap_err(),
+            "input 0 has no prevout"
+        );
+
+        value["vin"][0]["sequence"] = 0.into();
+        assert!(parse_esplora_transaction(&value.to_string())
+            .unwrap_err()
+            .starts_with("transaction does not match txid"));
+    }
+
+    #[cfg(feature = "esplora")]
+    mod client {
+        use super::super::*;
+        use crate::{
+            batch::analyze_transactions, emulator::emulate, prevouts::PrevoutLookup, WalletType,
+        };
+        use bitcoin::{Transaction, TxOut, Txid};
+        use serde_json::{json, Value};
+        use std::{
+            collections::HashMap,
+            sync::{
+                atomic::{AtomicUsize, Ordering},
+                Arc,
+            },
+            thread,
+        };
+
+        /// A transaction as returned by `GET /tx/:txid`
+        fn esplora_json(
+            tx: &Transaction,
+            prevouts: &[Option<TxOut>],
+            height: Option<u32>,
+        ) -> Value {
+            let output = |txout: &TxOut| {
+                json!({
+                    "scriptpubkey": txout.script_pubkey.to_hex_string(),
+                    "value": txout.value.to_sat(),
+                })
+            };
+            let vin = tx
+                .input
+                .iter()
+                .zip(prevouts)
+                .map(|(txin, prevout)| {
+                    json!({
+                        "txid": txin.previous_output.txid.to_string(),
+                        "vout": txin.previous_output.vout,
+                        "prevout": prevout.as_ref().map(output),
+                        "scriptsig": txin.script_sig.to_hex_string(),
+                        "witness": txin.witness.iter().map(hex::encode).collect::<Vec<_>>(),
+                        "is_coinbase": false,
+                        "sequence": txin.sequence.0,
+                    })
+                })
+                .collect::<Vec<_>>();
+            json!({
+                "txid": tx.compute_txid().to_string(),
+                "version": tx.version.0,
+                "locktime": tx.lock_time.to_consensus_u32(),
+                "vin": vin,
+                "vout": tx.output.iter().map(output).collect::<Vec<_>>(),
+                "status": match height {
+                    Some(height) => json!({ "confirmed": true, "block_height": height }),
+                    None => json!({ "confirmed": false }),
+                },
+            })
+        }
+
+        /// Serves the transactions until the test exits, counting the requests
+        fn serve(txs: HashMap<Txid, Value>) -> (String, Arc<AtomicUsize>) {
+            let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/api", server.server_addr());
+            let requests = Arc::new(AtomicUsize::new(0));
+            let counter = requests.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let tx = request
+                        .url()
+                        .strip_prefix("/api/tx/")
+                        .and_then(|txid| txid.parse::<Txid>().ok())
+                        .and_then(|txid| txs.get(&txid));
+                    let response = match tx {
+                        Some(tx) => tiny_http::Response::from_string(tx.to_string()),
+                        None => tiny_http::Response::from_string("Transaction not found")
+                            .with_status_code(404),
+                    };
+                    request.respond(response).unwrap();
+                }
+            });
+            (url, requests)
+        }
+
+        #[test]
+        fn test_esplora_client() {
+            let labeled = emulate(WalletType::Trezor, 5).unwrap();
+            let tx = labeled.tx;
+            let prevouts = tx
+                .input
+                .iter()
+                .map(|txin| {
+                    labeled
+                        .prev_txs
+                        .iter()
+                        .find(|prev_tx| prev_tx.compute_txid() == txin.previous_output.txid)
+                        .map(|prev_tx| prev_tx.output[txin.previous_output.vout as usize].clone())
+                })
+                .collect::<Vec<_>>();
+            let mut txs = HashMap::new();
+            txs.insert(tx.compute_txid(), esplora_json(&tx, &prevouts, None));
+            for (i, prev_tx) in labeled.prev_txs.iter().enumerate() {
+                let no_prevouts = vec![None; prev_tx.input.len()];
+                let json = esplora_json(prev_tx, &no_prevouts, Some(150 + i as u32));
+                txs.insert(prev_tx.compute_txid(), json);
+            }
+            let (url, requests) = serve(txs);
+            let client = EsploraClient::new(&url);
+
+            let esplora = client.transaction(&tx.compute_txid()).unwrap();
+            assert_eq!(esplora.height, None);
+            assert_eq!(esplora.resolved.tx, tx);
+            let heights = esplora
+                .resolved
+                .prevouts
+                .iter()
+                .map(|prevout| prevout.height)
+                .collect::<Vec<_>>();
+            let expected = tx
+                .input
+                .iter()
+                .map(|txin| {
+                    labeled
+                        .prev_txs
+                        .iter()
+                        .position(|prev_tx| prev_tx.compute_txid() == txin.previous_output.txid)
+                        .map(|i| 150 + i as u32)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(heights, expected);
+            assert!(esplora
+                .resolved
+                .detect_wallet()
+                .0
+                .contains(&WalletType::Trezor));
+            assert_eq!(requests.load(Ordering::SeqCst), 1 + labeled.prev_txs.len());
+
+            // Confirmed parents are cached
+            let results = analyze_transactions([&tx], &client);
+            assert!(results[0].is_ok());
+            assert_eq!(requests.load(Ordering::SeqCst), 1 + labeled.prev_txs.len());
+
+            let unknown = Txid::from_raw_hash(bitcoin::hashes::Hash::all_zeros());
+            assert!(client
+                .transaction(&unknown)
+                .unwrap_err()
+                .contains("not found"));
+            assert_eq!(
+                client.prevout(&bitcoin::OutPoint::new(unknown, 0)),
+                Ok(None)
+            );
+        }
+    }
+}
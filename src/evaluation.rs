@@ -0,0 +1,336 @@
+//! Measuring how well wallet detection performs on labeled transactions.
+
+use std::{collections::HashMap, fmt, path::Path};
+
+use crate::{detect_wallet, fixtures::load_fixtures, rules::LabeledTransaction, WalletType};
+
+/// Precision and recall of detection for a single wallet
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct WalletMetrics {
+    pub wallet: WalletType,
+    /// Transactions of this wallet detected as this wallet
+    pub true_positives: u64,
+    /// Transactions of other wallets detected as this wallet
+    pub false_positives: u64,
+    /// Transactions of this wallet detected as something else
+    pub false_negatives: u64,
+    /// None if no transaction was detected as this wallet
+    pub precision: Option<f32>,
+    /// None if no transaction of this wallet was evaluated
+    pub recall: Option<f32>,
+}
+
+/// A cell of the confusion matrix
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct ConfusionEntry {
+    pub actual: WalletType,
+    pub predicted: WalletType,
+    pub count: u64,
+}
+
+/// A transaction that was not detected as the wallet it is labeled with
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct Misclassification {
+    pub txid: String,
+    pub actual: WalletType,
+    /// Every wallet detection could not rule out
+    pub detected: Vec<WalletType>,
+}
+
+/// Results of running detection over a labeled corpus
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct Evaluation {
+    /// Number of transactions evaluated
+    pub total: u64,
+    /// Number of transactions detected as exactly the wallet they are labeled with
+    pub correct: u64,
+    /// Metrics for every wallet that was either a label or a prediction
+    pub per_wallet: Vec<WalletMetrics>,
+    /// Non-empty cells only, ordered by actual then predicted wallet
+    pub confusion_matrix: Vec<ConfusionEntry>,
+    pub misclassified: Vec<Misclassification>,
+}
+
+impl Evaluation {
+    /// Fraction of transactions detected correctly
+    pub fn accuracy(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.correct as f32 / self.total as f32
+    }
+}
+
+/// Collapses the wallets detection could not rule out into a single prediction.
+/// If more than one wallet remains the result is unclear.
+fn predicted_wallet(detected: &[WalletType]) -> WalletType {
+    match detected {
+        [wallet] => *wallet,
+        [] => WalletType::Other,
+        _ => WalletType::Unclear,
+    }
+}
+
+/// Runs `detect_wallet` over every labeled transaction and compares the result with its label.
+/// Fails if a transaction spends an output missing from its previous transactions.
+pub fn evaluate(samples: &[LabeledTransaction]) -> Result<Evaluation, String> {
+    let mut confusion: HashMap<(WalletType, WalletType), u64> = HashMap::new();
+    let mut misclassified = Vec::new();
+
+    for sample in samples {
+        let (detected, _) = detect_wallet(&sample.tx, &sample.prev_txs)?;
+        let mut detected = detected.into_iter().collect::<Vec<_>>();
+        detected.sort();
+
+        let predicted = predicted_wallet(&detected);
+        *confusion.entry((sample.wallet, predicted)).or_default() += 1;
+        if predicted != sample.wallet {
+            misclassified.push(Misclassification {
+                txid: sample.tx.compute_txid().to_string(),
+                actual: sample.wallet,
+                detected,
+            });
+        }
+    }
+
+    let per_wallet = WalletType::ALL
+        .into_iter()
+        .filter_map(|wallet| {
+            let count = |matches: &dyn Fn(WalletType, WalletType) -> bool| {
+                confusion
+                    .iter()
+                    .filter(|((actual, predicted), _)| matches(*actual, *predicted))
+                    .map(|(_, count)| *count)
+                    .sum::<u64>()
+            };
+            let true_positives = count(&|a, p| a == wallet && p == wallet);
+            let false_positives = count(&|a, p| a != wallet && p == wallet);
+            let false_negatives = count(&|a, p| a == wallet && p != wallet);
+            let ratio = |denominator: u64| {
+                (denominator > 0).then(|| true_positives as f32 / denominator as f32)
+            };
+            (true_positives + false_positives + false_negatives > 0).then(|| WalletMetrics {
+                wallet,
+                true_positives,
+                false_positives,
+                false_negatives,
+                precision: ratio(true_positives + false_positives),
+                recall: ratio(true_positives + false_negatives),
+            })
+        })
+        .collect();
+
+    let mut confusion_matrix = confusion
+        .into_iter()
+        .map(|((actual, predicted), count)| ConfusionEntry {
+            actual,
+            predicted,
+            count,
+        })
+        .collect::<Vec<_>>();
+    confusion_matrix.sort_by_key(|entry| (entry.actual, entry.predicted));
+
+    Ok(Evaluation {
+        total: samples.len() as u64,
+        correct: samples.len() as u64 - misclassified.len() as u64,
+        per_wallet,
+        confusion_matrix,
+        misclassified,
+    })
+}
+
+/// Runs `detect_wallet` over every fixture in a directory, see [`crate::fixtures`]
+pub fn evaluate_fixtures(dir: &Path) -> Result<Evaluation, String> {
+    let samples = load_fixtures(dir)?
+        .into_iter()
+        .map(|fixture| fixture.labeled)
+        .collect::<Vec<_>>();
+    evaluate(&samples)
+}
+
+/// A plain text report, suitable for printing from tests or a command line tool
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |value: Option<f32>| match value {
+            Some(value) => format!("{:.1}%", value * 100.0),
+            None => "-".to_string(),
+        };
+
+        writeln!(
+            f,
+            "accuracy: {} ({}/{})",
+            percent(Some(self.accuracy())),
+            self.correct,
+            self.total
+        )?;
+
+        writeln!(f, "\n{:<12} {:>9} {:>9}", "wallet", "precision", "recall")?;
+        for metrics in self.per_wallet.iter() {
+            writeln!(
+                f,
+                "{:<12} {:>9} {:>9}",
+                metrics.wallet.to_string(),
+                percent(metrics.precision),
+                percent(metrics.recall)
+            )?;
+        }
+
+        writeln!(f, "\n{:<12} {:<12} {:>5}", "actual", "predicted", "count")?;
+        for entry in self.confusion_matrix.iter() {
+            writeln!(
+                f,
+                "{:<12} {:<12} {:>5}",
+                entry.actual.to_string(),
+                entry.predicted.to_string(),
+                entry.count
+            )?;
+        }
+
+        if !self.misclassified.is_empty() {
+            writeln!(f, "\nmisclassified:")?;
+            for miss in self.misclassified.iter() {
+                let detected = miss
+                    .detected
+                    .iter()
+                    .map(|wallet| wallet.to_string())
+                    .collect::<Vec<_>>();
+                writeln!(
+                    f,
+                    "{} {} detected as [{}]",
+                    miss.txid,
+                    miss.actual,
+                    detected.join(", ")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::p2sh_payment;
+    use bitcoin::{absolute::LockTime, transaction::Version, Sequence};
+
+    /// A transaction spending a p2wpkh output, paying to a p2sh output with change back to p2wpkh
+    fn labeled(
+        seed: u64,
+        version: Version,
+        lock_time: LockTime,
+        wallet: WalletType,
+    ) -> LabeledTransaction {
+        let (tx, prev_txs) = p2sh_payment(seed)
+            .version(version)
+            .sequence(Sequence::ENABLE_RBF_NO_LOCKTIME)
+            .lock_time(lock_time)
+            .build();
+        LabeledTransaction {
+            tx,
+            prev_txs,
+            wallet,
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let samples = vec![
+            // Detected as BlueWallet
+            labeled(1, Version::TWO, LockTime::ZERO, WalletType::BlueWallet),
+            labeled(2, Version::TWO, LockTime::ZERO, WalletType::BlueWallet),
+            labeled(3, Version::TWO, LockTime::ZERO, WalletType::Exodus),
+            // Detected as either Ledger or Trezor
+            labeled(4, Version::ONE, LockTime::ZERO, WalletType::Trezor),
+        ];
+        let evaluation = evaluate(&samples).unwrap();
+        assert_eq!(evaluation.total, 4);
+        assert_eq!(evaluation.correct, 2);
+        assert_eq!(evaluation.accuracy(), 0.5);
+
+        let blue_wallet = evaluation
+            .per_wallet
+            .iter()
+            .find(|m| m.wallet == WalletType::BlueWallet)
+            .unwrap();
+        assert_eq!(blue_wallet.true_positives, 2);
+        assert_eq!(blue_wallet.false_positives, 1);
+        assert_eq!(blue_wallet.precision, Some(2.0 / 3.0));
+        assert_eq!(blue_wallet.recall, Some(1.0));
+
+        let trezor = evaluation
+            .per_wallet
+            .iter()
+            .find(|m| m.wallet == WalletType::Trezor)
+            .unwrap();
+        assert_eq!(trezor.false_negatives, 1);
+        assert_eq!(trezor.precision, None);
+        assert_eq!(trezor.recall, Some(0.0));
+
+        assert_eq!(
+            evaluation.confusion_matrix,
+            vec![
+                ConfusionEntry {
+                    actual: WalletType::BlueWallet,
+                    predicted: WalletType::BlueWallet,
+                    count: 2,
+                },
+                ConfusionEntry {
+                    actual: WalletType::Exodus,
+                    predicted: WalletType::BlueWallet,
+                    count: 1,
+                },
+                ConfusionEntry {
+                    actual: WalletType::Trezor,
+                    predicted: WalletType::Unclear,
+                    count: 1,
+                },
+            ]
+        );
+
+        assert_eq!(evaluation.misclassified.len(), 2);
+        assert_eq!(
+            evaluation.misclassified[1].txid,
+            samples[3].tx.compute_txid().to_string()
+        );
+        assert_eq!(
+            evaluation.misclassified[1].detected,
+            vec![WalletType::Trezor, WalletType::Ledger]
+        );
+        assert!(evaluation.to_string().contains(&format!(
+            "{} Trezor detected as [Trezor, Ledger]",
+            samples[3].tx.compute_txid()
+        )));
+    }
+
+    #[test]
+    fn test_evaluate_fixtures() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/wallets");
+        let evaluation = evaluate_fixtures(&dir).unwrap();
+        assert_eq!(evaluation.total, 5, "{}", evaluation);
+        assert_eq!(evaluation.correct, 5, "{}", evaluation);
+        assert!(evaluation.misclassified.is_empty());
+
+        let wallets = [
+            WalletType::Electrum,
+            WalletType::BlueWallet,
+            WalletType::Exodus,
+            WalletType::Trezor,
+            WalletType::Ledger,
+        ];
+        assert_eq!(
+            evaluation.confusion_matrix,
+            wallets
+                .into_iter()
+                .map(|wallet| ConfusionEntry {
+                    actual: wallet,
+                    predicted: wallet,
+                    count: 1,
+                })
+                .collect::<Vec<_>>()
+        );
+    }
+}
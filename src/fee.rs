@@ -0,0 +1,217 @@
+use bitcoin::{ecdsa::Signature as EcdsaSignature, Amount, Transaction};
+
+use crate::util::{
+    extract_signatures_from_scriptsig, extract_signatures_from_witness, TxOutWithOutpoint,
+};
+
+/// Fee estimators whose signature size assumptions we can reconstruct.
+/// Wallets estimate the size of a transaction before signing it, so the feerate they targeted
+/// is only round when computed against their own worst-case estimate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Enum))]
+pub enum FeeEstimator {
+    /// Bitcoin Core's dummy signer, 71 byte (low-R) ECDSA sigs and 64 byte schnorr sigs
+    BitcoinCore,
+    /// BDK's max satisfaction weight, 73 byte ECDSA sigs and 65 byte schnorr sigs
+    Bdk,
+    /// Electrum's 72 byte ECDSA placeholder sigs and 64 byte schnorr sigs
+    Electrum,
+    /// LDK's weight constants, 73 byte ECDSA sigs and 64 byte schnorr sigs
+    Ldk,
+}
+
+impl FeeEstimator {
+    const ALL: [FeeEstimator; 4] = [
+        FeeEstimator::BitcoinCore,
+        FeeEstimator::Bdk,
+        FeeEstimator::Electrum,
+        FeeEstimator::Ldk,
+    ];
+
+    /// Assumed ECDSA signature length including the sighash byte
+    fn ecdsa_sig_len(&self) -> usize {
+        match self {
+            FeeEstimator::BitcoinCore => 71,
+            FeeEstimator::Electrum => 72,
+            FeeEstimator::Bdk | FeeEstimator::Ldk => 73,
+        }
+    }
+
+    /// Assumed schnorr signature length including the optional sighash byte
+    fn schnorr_sig_len(&self) -> usize {
+        match self {
+            FeeEstimator::Bdk => 65,
+            FeeEstimator::BitcoinCore | FeeEstimator::Electrum | FeeEstimator::Ldk => 64,
+        }
+    }
+}
+
+/// Returns the fee paid by the transaction, or None if the outputs exceed the inputs
+pub(crate) fn get_fee(tx: &Transaction, prev_outs: &[TxOutWithOutpoint]) -> Option<Amount> {
+    let input_sum = prev_outs.iter().try_fold(Amount::ZERO, |acc, prev_out| {
+        acc.checked_add(prev_out.txout.value)
+    })?;
+    let output_sum = tx
+        .output
+        .iter()
+        .try_fold(Amount::ZERO, |acc, txout| acc.checked_add(txout.value))?;
+    input_sum.checked_sub(output_sum)
+}
+
+/// Reconstructs the vsize the estimator would have assumed before signing,
+/// by swapping every signature for one of the estimator's worst-case length
+pub(crate) fn estimated_vsize(tx: &Transaction, estimator: FeeEstimator) -> u64 {
+    let mut weight = tx.weight().to_wu() as i64;
+    for txin in tx.input.iter() {
+        // Witness bytes are discounted, scriptSig bytes are not. Nested segwit inputs have both,
+        // with the signatures in the witness. Only ECDSA signatures are found in a scriptSig,
+        // other pushes that happen to start like one keep their size.
+        let signatures = extract_signatures_from_scriptsig(&txin.script_sig)
+            .into_iter()
+            .filter(|sig| EcdsaSignature::from_slice(sig).is_ok())
+            .map(|sig| (sig, 4))
+            .chain(
+                extract_signatures_from_witness(&txin.witness)
+                    .into_iter()
+                    .map(|sig| (sig, 1)),
+            );
+        for (sig, scale) in signatures {
+            let assumed_len = if EcdsaSignature::from_slice(&sig).is_ok() {
+                estimator.ecdsa_sig_len()
+            } else {
+                estimator.schnorr_sig_len()
+            };
+            weight += (assumed_len as i64 - sig.len() as i64) * scale;
+        }
+    }
+
+    (weight.max(0) as u64).div_ceil(4)
+}
+
+/// Returns the fee estimators whose reconstructed vsize yields a whole sat/vB feerate.
+/// Fractional feerates such as 1.5 sat/vB are never considered round.
+pub(crate) fn get_round_feerate_estimators(
+    tx: &Transaction,
+    prev_outs: &[TxOutWithOutpoint],
+) -> Vec<FeeEstimator> {
+    let Some(fee) = get_fee(tx, prev_outs) else {
+        return vec![];
+    };
+    if fee == Amount::ZERO {
+        return vec![];
+    }
+
+    FeeEstimator::ALL
+        .into_iter()
+        .filter(|estimator| {
+            let vsize = estimated_vsize(tx, *estimator);
+            vsize != 0 && fee.to_sat() % vsize == 0
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{ScriptType, TxBuilder};
+    use bitcoin::{
+        hashes::Hash,
+        secp256k1::{Message, Secp256k1, SecretKey},
+        sighash::EcdsaSighashType,
+        OutPoint, PublicKey, ScriptBuf, Sequence, TxIn, TxOut, Txid, WPubkeyHash, Witness,
+    };
+
+    fn p2wpkh_spend() -> (Transaction, Vec<TxOutWithOutpoint>) {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[0x01; 32]).unwrap();
+        let pubkey = PublicKey::new(secret_key.public_key(&secp));
+        let sig = bitcoin::ecdsa::Signature {
+            signature: secp.sign_ecdsa_low_r(&Message::from_digest([0x02; 32]), &secret_key),
+            sighash_type: EcdsaSighashType::All,
+        };
+        let spk = ScriptBuf::new_p2wpkh(&WPubkeyHash::hash(&pubkey.to_bytes()));
+        let outpoint = OutPoint::new(Txid::all_zeros(), 0);
+
+        let tx = Transaction {
+            version: bitcoin::transaction::Version::TWO,
+            lock_time: bitcoin::locktime::absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::p2wpkh(&sig, &pubkey.inner),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: spk.clone(),
+            }],
+        };
+        let prev_outs = vec![TxOutWithOutpoint {
+            txout: TxOut {
+                value: Amount::from_sat(100_000),
+                script_pubkey: spk,
+            },
+            outpoint,
+            height: None,
+        }];
+        (tx, prev_outs)
+    }
+
+    #[test]
+    fn test_round_feerate_for_core_estimate() {
+        let (mut tx, prev_outs) = p2wpkh_spend();
+        let fee = 3 * estimated_vsize(&tx, FeeEstimator::BitcoinCore);
+        tx.output[0].value = Amount::from_sat(100_000 - fee);
+
+        assert_eq!(get_fee(&tx, &prev_outs), Some(Amount::from_sat(fee)));
+        let estimators = get_round_feerate_estimators(&tx, &prev_outs);
+        assert!(estimators.contains(&FeeEstimator::BitcoinCore));
+    }
+
+    #[test]
+    fn test_no_round_feerate() {
+        let (mut tx, prev_outs) = p2wpkh_spend();
+        let fee = 3 * estimated_vsize(&tx, FeeEstimator::Bdk) + 1;
+        tx.output[0].value = Amount::from_sat(100_000 - fee);
+
+        assert!(get_round_feerate_estimators(&tx, &prev_outs).is_empty());
+    }
+
+    #[test]
+    fn test_estimated_vsize_of_nested_segwit() {
+        let (tx, _) = TxBuilder::new(0)
+            .input(Amount::from_sat(100_000), ScriptType::P2shP2wpkh)
+            .output(Amount::from_sat(99_000), ScriptType::P2wpkh)
+            .build();
+        // The scriptSig only pushes the redeem script, the signature is in the witness
+        let sig_len = tx.input[0].witness.nth(0).unwrap().len() as u64;
+        assert_eq!(
+            estimated_vsize(&tx, FeeEstimator::Bdk),
+            (tx.weight().to_wu() + 73 - sig_len).div_ceil(4)
+        );
+    }
+
+    #[test]
+    fn test_estimated_vsize_keeps_other_scriptsig_pushes() {
+        let (mut tx, _) = p2wpkh_spend();
+        // Starts like a DER signature, but is not one
+        tx.input[0].script_sig = ScriptBuf::builder().push_slice([0x30; 40]).into_script();
+        let sig_len = tx.input[0].witness.nth(0).unwrap().len() as u64;
+        assert_eq!(
+            estimated_vsize(&tx, FeeEstimator::Bdk),
+            (tx.weight().to_wu() + FeeEstimator::Bdk.ecdsa_sig_len() as u64 - sig_len).div_ceil(4)
+        );
+    }
+
+    #[test]
+    fn test_estimated_vsize_is_worst_case() {
+        let (tx, _) = p2wpkh_spend();
+        let core = estimated_vsize(&tx, FeeEstimator::BitcoinCore);
+        let electrum = estimated_vsize(&tx, FeeEstimator::Electrum);
+        let bdk = estimated_vsize(&tx, FeeEstimator::Bdk);
+        assert!(tx.vsize() as u64 <= core);
+        assert!(core <= electrum);
+        assert!(electrum <= bdk);
+        assert_eq!(bdk, estimated_vsize(&tx, FeeEstimator::Ldk));
+    }
+}
@@ -0,0 +1,244 @@
+//! Canonical structural fingerprints of transactions, so transactions can be grouped by the wallet
+//! configuration that made them without deciding on a wallet label.
+
+use bitcoin::hashes::{sha256, Hash};
+
+use crate::{
+    global::{LockTimeClass, SequenceType},
+    heuristics::Heuristics,
+    input::{InputSortingType, SighashType},
+    output::{ChangeIndex, OutputStructureType},
+};
+
+/// Version of the canonical fingerprint format. Bump whenever the format changes,
+/// as ids from different versions are not comparable.
+pub const FINGERPRINT_VERSION: u8 = 1;
+/// Number of hex characters of the canonical fingerprint hash used as the id
+const FINGERPRINT_ID_LEN: usize = 12;
+/// Tag of a heuristic that cannot be determined yet, such as the signatures of an unsigned PSBT
+const UNKNOWN_TAG: &str = "?";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct Fingerprint {
+    /// Canonical fingerprint string, e.g. `wf1|v2|lt:height|seq:rbf|in:p2wpkh|...`
+    pub canonical: String,
+    /// Short hash of the canonical string
+    pub id: String,
+}
+
+pub(crate) fn locktime_tag(locktime_class: LockTimeClass) -> &'static str {
+    match locktime_class {
+        LockTimeClass::Zero => "zero",
+        LockTimeClass::BlockHeight => "height",
+        LockTimeClass::Timestamp => "time",
+    }
+}
+
+pub(crate) fn sequence_tag(sequence_type: SequenceType) -> &'static str {
+    match sequence_type {
+        SequenceType::Final => "final",
+        SequenceType::EnableLocktime => "locktime",
+        SequenceType::EnableRbf => "rbf",
+        SequenceType::RelativeLocktime => "relative",
+        SequenceType::Other => "other",
+    }
+}
+
+pub(crate) fn sighash_tag(sighash_type: SighashType) -> &'static str {
+    match sighash_type {
+        SighashType::Default => "default",
+        SighashType::All => "all",
+        SighashType::None => "none",
+        SighashType::Single => "single",
+        SighashType::AllPlusAnyoneCanPay => "all_acp",
+        SighashType::NonePlusAnyoneCanPay => "none_acp",
+        SighashType::SinglePlusAnyoneCanPay => "single_acp",
+        SighashType::Unknown => "unknown",
+    }
+}
+
+pub(crate) fn input_order_tag(input_order: InputSortingType) -> &'static str {
+    match input_order {
+        InputSortingType::Single => "single",
+        InputSortingType::Ascending => "asc",
+        InputSortingType::Descending => "desc",
+        InputSortingType::Bip69 => "bip69",
+        InputSortingType::Historical => "historical",
+        InputSortingType::Unknown => "unknown",
+    }
+}
+
+pub(crate) fn output_structure_tag(output_structure: OutputStructureType) -> Option<&'static str> {
+    match output_structure {
+        OutputStructureType::Bip69 => Some("bip69"),
+        // Output count and change position are captured elsewhere
+        OutputStructureType::Single
+        | OutputStructureType::Double
+        | OutputStructureType::Multi
+        | OutputStructureType::ChangeLast => None,
+    }
+}
+
+pub(crate) fn change_tag(change_index: ChangeIndex, output_count: usize) -> &'static str {
+    match change_index {
+        ChangeIndex::NoChange => "none",
+        ChangeIndex::Inconclusive => "unknown",
+        ChangeIndex::Found(idx) if idx + 1 == output_count => "last",
+        ChangeIndex::Found(0) => "first",
+        ChangeIndex::Found(_) => "middle",
+    }
+}
+
+/// Joins the distinct tags, sorted so the order they were seen in does not matter
+fn join_tags(tags: impl IntoIterator<Item = String>) -> String {
+    let mut tags = tags.into_iter().collect::<Vec<_>>();
+    tags.sort();
+    tags.dedup();
+    if tags.is_empty() {
+        return "-".to_string();
+    }
+    tags.join(",")
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl Heuristics {
+    /// Returns the canonical structural fingerprint of the transaction.
+    /// Two transactions made by the same wallet configuration share a fingerprint.
+    pub fn fingerprint(&self) -> Fingerprint {
+        let fields = [
+            format!("wf{}", FINGERPRINT_VERSION),
+            format!("v{}", self.tx_version.0),
+            format!("lt:{}", locktime_tag(self.locktime_class)),
+            format!(
+                "seq:{}",
+                join_tags(
+                    self.sequence_profile
+                        .iter()
+                        .map(|s| sequence_tag(*s).to_string())
+                )
+            ),
+            format!(
+                "in:{}",
+                join_tags(self.input_types.iter().map(|t| t.to_string()))
+            ),
+            format!(
+                "out:{}",
+                join_tags(self.output_types.iter().map(|t| t.to_string()))
+            ),
+            format!(
+                "inord:{}",
+                join_tags(
+                    self.input_order
+                        .iter()
+                        .map(|o| input_order_tag(*o).to_string())
+                )
+            ),
+            format!(
+                "outord:{}",
+                join_tags(
+                    self.output_structure
+                        .iter()
+                        .filter_map(|o| output_structure_tag(*o))
+                        .map(|tag| tag.to_string())
+                )
+            ),
+            format!(
+                "chg:{}",
+                change_tag(self.change_index, self.output_types.len())
+            ),
+            format!(
+                "lowr:{}",
+                self.low_r_grinding
+                    .map_or(UNKNOWN_TAG.to_string(), |low_r| u8::from(low_r).to_string())
+            ),
+            format!(
+                "sh:{}",
+                self.sighash_types
+                    .as_ref()
+                    .map_or(UNKNOWN_TAG.to_string(), |sighash_types| join_tags(
+                        sighash_types.iter().map(|s| sighash_tag(*s).to_string())
+                    ))
+            ),
+        ];
+        let canonical = fields.join("|");
+        let hash = sha256::Hash::hash(canonical.as_bytes()).to_string();
+
+        Fingerprint {
+            id: hash[..FINGERPRINT_ID_LEN].to_string(),
+            canonical,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{change::ChangeHints, util::TxOutWithOutpoint};
+    use bitcoin::{
+        hashes::Hash, transaction::Version, Amount, OutPoint, ScriptBuf, ScriptHash, Sequence,
+        Transaction, TxIn, TxOut, Txid, WPubkeyHash, Witness,
+    };
+
+    fn heuristics(version: Version, inputs: &[u64], outputs: &[u64]) -> Heuristics {
+        let prev_outs = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, value)| TxOutWithOutpoint {
+                txout: TxOut {
+                    value: Amount::from_sat(*value),
+                    script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(
+                        [i as u8; 20],
+                    )),
+                },
+                outpoint: OutPoint::new(Txid::from_byte_array([i as u8; 32]), 0),
+                height: None,
+            })
+            .collect::<Vec<_>>();
+        let tx = Transaction {
+            version,
+            lock_time: bitcoin::locktime::absolute::LockTime::ZERO,
+            input: prev_outs
+                .iter()
+                .map(|prev_out| TxIn {
+                    previous_output: prev_out.outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(outputs[0]),
+                    script_pubkey: ScriptBuf::new_p2sh(&ScriptHash::from_byte_array([9; 20])),
+                },
+                TxOut {
+                    value: Amount::from_sat(outputs[1]),
+                    script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([8; 20])),
+                },
+            ],
+        };
+        Heuristics::from_prevouts(&tx, &prev_outs, &ChangeHints::default())
+    }
+
+    #[test]
+    fn test_canonical_fingerprint() {
+        let fingerprint =
+            heuristics(Version::TWO, &[20_000, 30_000], &[10_000, 38_765]).fingerprint();
+        assert_eq!(
+            fingerprint.canonical,
+            "wf1|v2|lt:zero|seq:rbf|in:p2wpkh|out:p2sh,p2wpkh|inord:asc,bip69|outord:bip69|chg:last|lowr:1|sh:-"
+        );
+        assert_eq!(fingerprint.id.len(), FINGERPRINT_ID_LEN);
+    }
+
+    #[test]
+    fn test_same_configuration_same_fingerprint() {
+        let a = heuristics(Version::TWO, &[20_000, 30_000], &[10_000, 38_765]).fingerprint();
+        let b = heuristics(Version::TWO, &[40_000, 50_000], &[30_000, 59_321]).fingerprint();
+        assert_eq!(a, b);
+
+        let c = heuristics(Version::ONE, &[20_000, 30_000], &[10_000, 38_765]).fingerprint();
+        assert_ne!(a.id, c.id);
+    }
+}
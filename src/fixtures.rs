@@ -0,0 +1,207 @@
+//! Labeled transactions stored on disk, one JSON file per transaction.
+//!
+//! ```json
+//! {
+//!   "description": "Electrum transaction 5d857401...",
+//!   "wallet": "Electrum",
+//!   "tx": "02000000...",
+//!   "prev_txs": ["01000000...", "02000000..."],
+//!   "expected_wallets": ["Electrum"],
+//!   "expected_heuristics": { "version": "2", "locktime": "height" }
+//! }
+//! ```
+//!
+//! `expected_wallets` defaults to `[wallet]`. Keys of `expected_heuristics` are the trait names used
+//! for clustering, see [`crate::clustering::distance`].
+//! Fixtures may also carry the outputs of the reference implementation, see [`crate::conformance`].
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bitcoin::{consensus::encode::deserialize_hex, Transaction};
+use serde::Deserialize;
+
+use crate::{
+    change::ChangeHints, clustering::Traits, detect_wallet_from_prevouts, heuristics::Heuristics,
+    rules::LabeledTransaction, util::get_prevouts, WalletType,
+};
+
+#[derive(Debug, Deserialize)]
+struct FixtureFile {
+    #[serde(default)]
+    description: String,
+    wallet: String,
+    tx: String,
+    prev_txs: Vec<String>,
+    expected_wallets: Option<Vec<String>>,
+    #[serde(default)]
+    expected_heuristics: BTreeMap<String, String>,
+    #[serde(default)]
+    reference: BTreeMap<String, String>,
+    #[serde(default)]
+    divergences: BTreeMap<String, String>,
+}
+
+/// A labeled transaction loaded from a fixture file
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    /// The file the fixture was loaded from
+    pub path: PathBuf,
+    pub description: String,
+    pub labeled: LabeledTransaction,
+    /// The wallets `detect_wallet` is expected to return
+    pub expected_wallets: HashSet<WalletType>,
+    /// Expected value of each listed trait, e.g. `version` => `2`
+    pub expected_heuristics: BTreeMap<String, String>,
+    /// Outputs of the reference Python implementation, see [`crate::conformance`]
+    pub reference: BTreeMap<String, String>,
+    /// Reason we intentionally differ from the reference, by heuristic
+    pub divergences: BTreeMap<String, String>,
+}
+
+impl Fixture {
+    /// Runs detection and the heuristics over the fixture,
+    /// returning a description of every way the result differs from what was expected
+    pub fn check(&self) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        let tx = &self.labeled.tx;
+        let prev_txs = &self.labeled.prev_txs;
+
+        let prev_outs = match get_prevouts(tx, prev_txs) {
+            Ok(prev_outs) => prev_outs,
+            Err(e) => return vec![e],
+        };
+        let (wallets, reasoning) = detect_wallet_from_prevouts(tx, &prev_outs);
+        if wallets != self.expected_wallets {
+            let mut expected = self.expected_wallets.iter().collect::<Vec<_>>();
+            let mut detected = wallets.iter().collect::<Vec<_>>();
+            expected.sort();
+            detected.sort();
+            mismatches.push(format!(
+                "expected wallets {:?}, detected {:?} because {:?}",
+                expected, detected, reasoning
+            ));
+        }
+
+        let heuristics = Heuristics::from_prevouts(tx, &prev_outs, &ChangeHints::default());
+        let traits = Traits::new(&heuristics);
+        for (name, expected) in self.expected_heuristics.iter() {
+            match traits.get(name) {
+                Some(actual) if actual == *expected => {}
+                Some(actual) => mismatches.push(format!(
+                    "expected {}={}, found {}={}",
+                    name, expected, name, actual
+                )),
+                None => mismatches.push(format!("unknown heuristic: {}", name)),
+            }
+        }
+
+        mismatches
+    }
+}
+
+fn parse_tx(hex: &str) -> Result<Transaction, String> {
+    deserialize_hex(hex).map_err(|e| format!("invalid transaction hex: {}", e))
+}
+
+fn parse_wallets(wallets: &[String]) -> Result<HashSet<WalletType>, String> {
+    wallets.iter().map(|wallet| wallet.parse()).collect()
+}
+
+/// Loads a single fixture file
+pub fn load_fixture(path: &Path) -> Result<Fixture, String> {
+    let with_path = |e: String| format!("{}: {}", path.display(), e);
+
+    let contents = fs::read_to_string(path).map_err(|e| with_path(e.to_string()))?;
+    let file: FixtureFile =
+        serde_json::from_str(&contents).map_err(|e| with_path(e.to_string()))?;
+
+    let wallet = file.wallet.parse().map_err(with_path)?;
+    let expected_wallets = match file.expected_wallets {
+        Some(wallets) => parse_wallets(&wallets).map_err(with_path)?,
+        None => HashSet::from([wallet]),
+    };
+    let tx = parse_tx(&file.tx).map_err(with_path)?;
+    let prev_txs = file
+        .prev_txs
+        .iter()
+        .map(|hex| parse_tx(hex))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(with_path)?;
+
+    // Every input must be resolvable
+    get_prevouts(&tx, &prev_txs).map_err(with_path)?;
+
+    Ok(Fixture {
+        path: path.to_path_buf(),
+        description: file.description,
+        labeled: LabeledTransaction {
+            tx,
+            prev_txs,
+            wallet,
+        },
+        expected_wallets,
+        expected_heuristics: file.expected_heuristics,
+        reference: file.reference,
+        divergences: file.divergences,
+    })
+}
+
+/// Loads every `.json` file in a directory, ordered by file name
+pub fn load_fixtures(dir: &Path) -> Result<Vec<Fixture>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut paths = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", dir.display(), e))?;
+    paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+    paths.sort();
+
+    paths.iter().map(|path| load_fixture(path)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixtures_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/wallets")
+    }
+
+    /// Checks every fixture, so adding a labeled example only requires adding a file
+    #[test]
+    fn test_wallet_fixtures() {
+        let fixtures = load_fixtures(&fixtures_dir()).unwrap();
+        assert!(!fixtures.is_empty());
+
+        let failures = fixtures
+            .iter()
+            .flat_map(|fixture| {
+                fixture
+                    .check()
+                    .into_iter()
+                    .map(move |mismatch| format!("{}: {}", fixture.path.display(), mismatch))
+            })
+            .collect::<Vec<_>>();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn test_load_invalid_fixture() {
+        let dir = std::env::temp_dir().join(format!("wallet-fixtures-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("unknown-wallet.json");
+        fs::write(
+            &path,
+            r#"{ "wallet": "Sparrow", "tx": "00", "prev_txs": [] }"#,
+        )
+        .unwrap();
+
+        let err = load_fixture(&path).unwrap_err();
+        assert!(err.contains("unknown wallet type: Sparrow"), "{}", err);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::HashSet;
 
-use bitcoin::Transaction;
+use bitcoin::{absolute::LockTime, Sequence, Transaction};
 
 use crate::util::TxOutWithOutpoint;
 
@@ -25,6 +25,65 @@ pub(crate) fn signals_rbf(tx: &Transaction) -> bool {
     tx.input.iter().any(|input| input.sequence.is_rbf())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Enum))]
+pub enum LockTimeClass {
+    /// Locktime is zero
+    Zero,
+    /// Locktime is a block height
+    BlockHeight,
+    /// Locktime is a unix timestamp
+    Timestamp,
+}
+
+/// Returns the class of the transaction's locktime
+pub(crate) fn get_locktime_class(tx: &Transaction) -> LockTimeClass {
+    match tx.lock_time {
+        LockTime::Blocks(height) if height.to_consensus_u32() == 0 => LockTimeClass::Zero,
+        LockTime::Blocks(_) => LockTimeClass::BlockHeight,
+        LockTime::Seconds(_) => LockTimeClass::Timestamp,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Enum))]
+pub enum SequenceType {
+    /// 0xffffffff, disables locktime and RBF
+    Final,
+    /// 0xfffffffe, enables locktime without signaling RBF
+    EnableLocktime,
+    /// 0xfffffffd, signals RBF
+    EnableRbf,
+    /// BIP 68 relative locktime
+    RelativeLocktime,
+    /// Any other value
+    Other,
+}
+
+impl From<Sequence> for SequenceType {
+    fn from(sequence: Sequence) -> Self {
+        match sequence {
+            Sequence::MAX => SequenceType::Final,
+            Sequence::ENABLE_LOCKTIME_NO_RBF => SequenceType::EnableLocktime,
+            Sequence::ENABLE_RBF_NO_LOCKTIME => SequenceType::EnableRbf,
+            sequence if sequence.is_relative_lock_time() => SequenceType::RelativeLocktime,
+            _ => SequenceType::Other,
+        }
+    }
+}
+
+/// Returns the distinct nSequence types used by the inputs, sorted
+pub(crate) fn get_sequence_profile(tx: &Transaction) -> Vec<SequenceType> {
+    let mut profile = tx
+        .input
+        .iter()
+        .map(|input| SequenceType::from(input.sequence))
+        .collect::<Vec<_>>();
+    profile.sort();
+    profile.dedup();
+    profile
+}
+
 // TODO: move this to output.rs
 /// Returns true if any output address matches any input address, indicating address reuse
 pub(crate) fn address_reuse(tx: &Transaction, prev_outs: &[TxOutWithOutpoint]) -> bool {
@@ -1,35 +1,60 @@
-use bitcoin::transaction::Version;
+use bitcoin::{transaction::Version, Psbt, Transaction, Txid};
 
 use crate::{
-    global::{address_reuse, is_anti_fee_sniping, signals_rbf},
+    change::{detect_change, ChangeDetection, ChangeHints},
+    coin_selection::{get_coin_selection, CoinSelection},
+    denomination::{get_output_amounts, OutputAmount},
+    fee::{get_round_feerate_estimators, FeeEstimator},
+    global::{
+        address_reuse, get_locktime_class, get_sequence_profile, is_anti_fee_sniping, signals_rbf,
+        LockTimeClass, SequenceType,
+    },
     input::{
-        get_input_order, get_input_types, low_order_r_grinding, mixed_input_types,
-        spending_spk_has_uncompressed_pubkey, InputSortingType,
+        get_input_order, get_input_types, get_sighash_types, low_order_r_grinding,
+        low_order_r_signatures, mixed_input_types, scriptsig_has_uncompressed_pubkey,
+        signature_sighash_types, spending_spk_has_uncompressed_pubkey, InputSortingType,
+        SighashType,
     },
     output::{
-        change_type_matched_inputs, get_change_index, get_output_structure, get_output_types,
-        ChangeIndex, ChangeTypeMatchedInputs, OutputStructureType,
+        change_type_matched_inputs, get_output_structure, get_output_types, optimal_change_index,
+        unnecessary_input_change_index, ChangeIndex, ChangeTypeMatchedInputs, OutputStructureType,
+    },
+    prevouts::{lookup_prevouts, PrevoutLookup},
+    psbt::{
+        psbt_finalized, psbt_prevouts, psbt_signatures, psbt_transaction, psbt_uncompressed_pubkeys,
     },
-    util::{OutputType, TxOutWithOutpoint},
+    util::{get_prevouts, OutputType, TxOutWithOutpoint},
 };
 
 #[derive(Debug)]
 #[cfg_attr(feature = "ffi", derive(uniffi::Object))]
 pub struct Heuristics {
+    /// The id of the transaction
+    pub txid: Txid,
     /* Global heuristics */
     /// The version of the transaction
     pub tx_version: Version,
+    /// The class of the transaction's locktime
+    pub locktime_class: LockTimeClass,
     /// Whether the transaction protects against fee sniping attacks
     /// https://bitcoinops.org/en/topics/fee-sniping/
     pub anti_fee_snipe: bool,
     // TODO: should this be a f32 probability?
-    /// Whether the transaction has any signatures with low order R values
+    /// Whether all ECDSA signatures of the transaction have low order R values,
+    /// None if some inputs are not signed yet
     /// https://bitcoinops.org/en/topics/low-r-grinding/
-    pub low_r_grinding: bool,
+    pub low_r_grinding: Option<bool>,
+    /// The distinct sighash types used by the signatures, None if some inputs are not signed yet
+    pub sighash_types: Option<Vec<SighashType>>,
     /// Whether the transaction has outputs that are the same as any inputs
     pub address_reuse: bool,
     /// Whether the transaction has inputs or outputs that are the same "type" as the change output
     pub maybe_same_change_type: ChangeTypeMatchedInputs,
+    /// The fee estimators whose worst-case size estimate yields a whole sat/vB feerate,
+    /// None if some inputs are not finalized yet, since their size is unknown
+    pub round_feerate_estimators: Option<Vec<FeeEstimator>>,
+    /// The inferred coin selection algorithm
+    pub coin_selection: CoinSelection,
     /* Input heuristics */
     /// Whether the transaction has inputs that are of different "types"
     pub mixed_input_types: bool,
@@ -37,17 +62,119 @@ pub struct Heuristics {
     pub input_types: Vec<OutputType>,
     /// Whether the transaction has inputs that are using uncompressed public keys
     pub spending_spk_has_uncompressed_pubkey: bool,
+    /// Whether a P2PKH input reveals an uncompressed public key, in its scriptSig or PSBT keys.
+    /// Only linted, since wallet detection follows the reference implementation.
+    pub reveals_uncompressed_pubkey: bool,
     /// Whether the transaction has inputs that are signals of RBF via BIP 125 (Replace-by-Fee)
     pub signals_rbf: bool,
+    /// The distinct nSequence types used by the inputs
+    pub sequence_profile: Vec<SequenceType>,
     /// The ordering of the inputs
     pub input_order: Vec<InputSortingType>,
     /* Output heuristics */
     /// The types of the outputs
     pub output_types: Vec<OutputType>,
+    /// The roundness of the output amounts
+    pub output_amounts: Vec<OutputAmount>,
     /// The structure of the outputs
     pub output_structure: Vec<OutputStructureType>,
     /// The index of the change output
     pub change_index: ChangeIndex,
+    /// The change probability of each output and the signals that fired for it
+    pub change_detection: ChangeDetection,
+    /// The change output according to the unnecessary input heuristic
+    pub unnecessary_input_change_index: ChangeIndex,
+    /// The change output according to the optimal change heuristic
+    pub optimal_change_index: ChangeIndex,
+}
+
+impl Heuristics {
+    /// Computes the heuristics given the previous outputs spent by each input, in input order
+    pub(crate) fn from_prevouts(
+        tx: &Transaction,
+        prev_txouts: &[TxOutWithOutpoint],
+        hints: &ChangeHints,
+    ) -> Self {
+        let change_detection = detect_change(tx, prev_txouts, hints);
+        Self {
+            txid: tx.compute_txid(),
+            tx_version: tx.version,
+            locktime_class: get_locktime_class(tx),
+            anti_fee_snipe: is_anti_fee_sniping(tx),
+            low_r_grinding: Some(low_order_r_grinding(tx)),
+            sighash_types: Some(get_sighash_types(tx)),
+            mixed_input_types: mixed_input_types(tx, prev_txouts),
+            maybe_same_change_type: change_type_matched_inputs(tx, prev_txouts),
+            input_types: get_input_types(tx, prev_txouts),
+            output_types: get_output_types(tx),
+            output_amounts: get_output_amounts(tx, hints.exchange_rate),
+            spending_spk_has_uncompressed_pubkey: spending_spk_has_uncompressed_pubkey(
+                tx,
+                prev_txouts,
+            ),
+            reveals_uncompressed_pubkey: scriptsig_has_uncompressed_pubkey(tx, prev_txouts),
+            signals_rbf: signals_rbf(tx),
+            sequence_profile: get_sequence_profile(tx),
+            address_reuse: address_reuse(tx, prev_txouts),
+            output_structure: get_output_structure(tx, prev_txouts),
+            change_index: change_detection.change_index(),
+            change_detection,
+            unnecessary_input_change_index: unnecessary_input_change_index(tx, prev_txouts),
+            optimal_change_index: optimal_change_index(tx, prev_txouts),
+            input_order: get_input_order(tx, prev_txouts),
+            round_feerate_estimators: Some(get_round_feerate_estimators(tx, prev_txouts)),
+            coin_selection: get_coin_selection(tx, prev_txouts),
+        }
+    }
+
+    /// Computes the heuristics resolving the previous outputs from `lookup`.
+    /// Returns None if any of them is unknown.
+    pub fn from_lookup<L: PrevoutLookup + ?Sized>(
+        tx: &Transaction,
+        lookup: &L,
+    ) -> Result<Option<Self>, String> {
+        Ok(lookup_prevouts(tx, lookup)?
+            .map(|prev_outs| Self::from_prevouts(tx, &prev_outs, &ChangeHints::default())))
+    }
+
+    /// Computes the heuristics of a PSBT before it is fully signed, taking the previous outputs
+    /// from the `witness_utxo` or `non_witness_utxo` of each input.
+    /// Signature heuristics are None until every input has a signature,
+    /// and the fee estimators until every input is finalized.
+    pub fn from_psbt(psbt: &Psbt) -> Result<Self, String> {
+        let tx = psbt_transaction(psbt);
+        let prev_outs = psbt_prevouts(psbt)?;
+        let mut heuristics = Self::from_prevouts(&tx, &prev_outs, &ChangeHints::default());
+        heuristics.reveals_uncompressed_pubkey |= psbt_uncompressed_pubkeys(psbt, &prev_outs);
+        let signatures = psbt_signatures(psbt);
+        heuristics.low_r_grinding = signatures
+            .as_ref()
+            .map(|signatures| low_order_r_signatures(signatures));
+        heuristics.sighash_types =
+            signatures.map(|signatures| signature_sighash_types(&signatures));
+        if !psbt_finalized(psbt) {
+            heuristics.round_feerate_estimators = None;
+        }
+        Ok(heuristics)
+    }
+}
+
+/// Error of the constructors exported over FFI
+#[cfg(feature = "uniffi")]
+#[derive(Debug, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum HeuristicsError {
+    /// The output spent by an input is not among the previous transactions
+    MissingPrevout(String),
+}
+
+#[cfg(feature = "uniffi")]
+impl std::fmt::Display for HeuristicsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeuristicsError::MissingPrevout(reason) => write!(f, "{}", reason),
+        }
+    }
 }
 
 #[cfg(feature = "uniffi")]
@@ -58,81 +185,164 @@ impl Heuristics {
     pub fn new(
         tx: std::sync::Arc<bitcoin_ffi::Transaction>,
         prev_txs: Vec<std::sync::Arc<bitcoin_ffi::Transaction>>,
-    ) -> Self {
-        // TODO do some validation on the previous transactions
-        let prev_txouts = tx
-            .0
-            .input
+    ) -> Result<Self, HeuristicsError> {
+        let prev_txs = prev_txs.iter().map(|tx| tx.0.clone()).collect::<Vec<_>>();
+        let prev_outs = get_prevouts(&tx.0, &prev_txs).map_err(HeuristicsError::MissingPrevout)?;
+        Ok(Self::from_prevouts(
+            &tx.0,
+            &prev_outs,
+            &ChangeHints::default(),
+        ))
+    }
+
+    #[cfg(feature = "uniffi")]
+    #[uniffi::constructor]
+    pub fn new_with_exchange_rate(
+        tx: std::sync::Arc<bitcoin_ffi::Transaction>,
+        prev_txs: Vec<std::sync::Arc<bitcoin_ffi::Transaction>>,
+        exchange_rate: f64,
+    ) -> Result<Self, HeuristicsError> {
+        let prev_txs = prev_txs.iter().map(|tx| tx.0.clone()).collect::<Vec<_>>();
+        let prev_outs = get_prevouts(&tx.0, &prev_txs).map_err(HeuristicsError::MissingPrevout)?;
+        let hints = ChangeHints {
+            exchange_rate: Some(exchange_rate),
+            ..Default::default()
+        };
+        Ok(Self::from_prevouts(&tx.0, &prev_outs, &hints))
+    }
+
+    #[cfg(feature = "uniffi")]
+    #[uniffi::constructor]
+    pub fn new_with_spending_txs(
+        tx: std::sync::Arc<bitcoin_ffi::Transaction>,
+        prev_txs: Vec<std::sync::Arc<bitcoin_ffi::Transaction>>,
+        spending_txs: Vec<std::sync::Arc<bitcoin_ffi::Transaction>>,
+    ) -> Result<Self, HeuristicsError> {
+        let prev_txs = prev_txs.iter().map(|tx| tx.0.clone()).collect::<Vec<_>>();
+        let prev_outs = get_prevouts(&tx.0, &prev_txs).map_err(HeuristicsError::MissingPrevout)?;
+        let spending_txs = spending_txs
             .iter()
-            .map(|txin| TxOutWithOutpoint {
-                txout: prev_txs
-                    .iter()
-                    .find(|prev_tx| prev_tx.compute_txid() == txin.previous_output.txid.to_string())
-                    .unwrap()
-                    .0
-                    .output[txin.previous_output.vout as usize]
-                    .clone(),
-                outpoint: txin.previous_output,
-            })
+            .map(|tx| tx.0.clone())
             .collect::<Vec<_>>();
-
-        Self {
-            tx_version: tx.0.version,
-            anti_fee_snipe: is_anti_fee_sniping(&tx.0),
-            low_r_grinding: low_order_r_grinding(&tx.0),
-            mixed_input_types: mixed_input_types(&tx.0, &prev_txouts),
-            maybe_same_change_type: change_type_matched_inputs(&tx.0, &prev_txouts),
-            input_types: get_input_types(&tx.0, &prev_txouts),
-            output_types: get_output_types(&tx.0),
-            spending_spk_has_uncompressed_pubkey: spending_spk_has_uncompressed_pubkey(
-                &tx.0,
-                &prev_txouts,
-            ),
-            signals_rbf: signals_rbf(&tx.0),
-            address_reuse: address_reuse(&tx.0, &prev_txouts),
-            output_structure: get_output_structure(&tx.0, &prev_txouts),
-            change_index: get_change_index(&tx.0, &prev_txouts),
-            input_order: get_input_order(&tx.0, &prev_txouts),
-        }
+        let hints = ChangeHints {
+            spending_txs: &spending_txs,
+            ..Default::default()
+        };
+        Ok(Self::from_prevouts(&tx.0, &prev_outs, &hints))
     }
 }
 
 #[cfg(not(feature = "uniffi"))]
 impl Heuristics {
+    /// Computes the heuristics of the transaction, given the previous transactions of its inputs.
+    /// Fails if the output spent by an input is not among them.
     #[cfg(not(feature = "uniffi"))]
-    pub fn new(tx: bitcoin::Transaction, prev_txs: Vec<bitcoin::Transaction>) -> Self {
-        // TODO do some validation on the previous transactions
-        let prev_txouts = tx
-            .input
-            .iter()
-            .map(|txin| TxOutWithOutpoint {
-                txout: prev_txs
-                    .iter()
-                    .find(|prev_tx| prev_tx.compute_txid() == txin.previous_output.txid)
-                    .unwrap()
-                    .output[txin.previous_output.vout as usize]
-                    .clone(),
-                outpoint: txin.previous_output,
-            })
-            .collect::<Vec<_>>();
+    pub fn new(
+        tx: bitcoin::Transaction,
+        prev_txs: Vec<bitcoin::Transaction>,
+    ) -> Result<Self, String> {
+        Ok(Self::from_prevouts(
+            &tx,
+            &get_prevouts(&tx, &prev_txs)?,
+            &ChangeHints::default(),
+        ))
+    }
 
-        Self {
-            tx_version: tx.version,
-            anti_fee_snipe: is_anti_fee_sniping(&tx),
-            low_r_grinding: low_order_r_grinding(&tx),
-            mixed_input_types: mixed_input_types(&tx, &prev_txouts),
-            maybe_same_change_type: change_type_matched_inputs(&tx, &prev_txouts),
-            input_types: get_input_types(&tx, &prev_txouts),
-            output_types: get_output_types(&tx),
-            spending_spk_has_uncompressed_pubkey: spending_spk_has_uncompressed_pubkey(
-                &tx,
-                &prev_txouts,
-            ),
-            signals_rbf: signals_rbf(&tx),
-            address_reuse: address_reuse(&tx, &prev_txouts),
-            output_structure: get_output_structure(&tx, &prev_txouts),
-            change_index: get_change_index(&tx, &prev_txouts),
-            input_order: get_input_order(&tx, &prev_txouts),
+    /// Like [`Heuristics::new`], additionally checking output amounts for fiat roundness
+    /// given the fiat price of one BTC
+    #[cfg(not(feature = "uniffi"))]
+    pub fn new_with_exchange_rate(
+        tx: bitcoin::Transaction,
+        prev_txs: Vec<bitcoin::Transaction>,
+        exchange_rate: f64,
+    ) -> Result<Self, String> {
+        let hints = ChangeHints {
+            exchange_rate: Some(exchange_rate),
+            ..Default::default()
+        };
+        Ok(Self::from_prevouts(
+            &tx,
+            &get_prevouts(&tx, &prev_txs)?,
+            &hints,
+        ))
+    }
+
+    /// Like [`Heuristics::new`], additionally using the transactions spending the outputs
+    /// of the transaction to find the change output, since change is spent by the same wallet
+    #[cfg(not(feature = "uniffi"))]
+    pub fn new_with_spending_txs(
+        tx: bitcoin::Transaction,
+        prev_txs: Vec<bitcoin::Transaction>,
+        spending_txs: Vec<bitcoin::Transaction>,
+    ) -> Result<Self, String> {
+        let hints = ChangeHints {
+            spending_txs: &spending_txs,
+            ..Default::default()
+        };
+        Ok(Self::from_prevouts(
+            &tx,
+            &get_prevouts(&tx, &prev_txs)?,
+            &hints,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        detect_wallet,
+        test_utils::{spend_prevouts, strategy},
+    };
+    use bitcoin::OutPoint;
+    use proptest::{collection::vec, prelude::*};
+
+    proptest! {
+        /// Transactions are attacker controlled, so no combination of scripts, signatures
+        /// and amounts may make the heuristics panic
+        #[test]
+        fn test_arbitrary_transactions_do_not_panic(
+            tx in strategy::transaction(),
+            prevouts in vec(strategy::txout(), 0..5),
+        ) {
+            let (tx, prev_txs) = spend_prevouts(tx, &prevouts);
+            let heuristics =
+                Heuristics::from_prevouts(&tx, &get_prevouts(&tx, &prev_txs).unwrap(), &ChangeHints::default());
+            let _ = heuristics.fingerprint();
+            let _ = detect_wallet(&tx, &prev_txs).unwrap();
+
+            let total = heuristics
+                .change_detection
+                .outputs
+                .iter()
+                .map(|output| output.probability)
+                .sum::<f32>();
+            prop_assert!(total <= 1.0 + f32::EPSILON);
+        }
+
+        /// Previous transactions are attacker controlled too, and may lack the spent outputs
+        #[test]
+        fn test_unresolved_prevouts_do_not_panic(
+            mut tx in strategy::transaction(),
+            prev_txs in vec(strategy::transaction(), 0..3),
+            linked in any::<bool>(),
+        ) {
+            // Point the inputs at the previous transactions, though not always at an output they have
+            if linked && !prev_txs.is_empty() {
+                for (i, txin) in tx.input.iter_mut().enumerate() {
+                    let prev_tx = &prev_txs[i % prev_txs.len()];
+                    let vout = txin.previous_output.vout % (prev_tx.output.len() as u32 + 1);
+                    txin.previous_output = OutPoint::new(prev_tx.compute_txid(), vout);
+                }
+            }
+            let resolved = get_prevouts(&tx, &prev_txs).is_ok();
+            prop_assert_eq!(detect_wallet(&tx, &prev_txs).is_ok(), resolved);
+            match Heuristics::new(tx, prev_txs) {
+                Ok(heuristics) => {
+                    let _ = heuristics.fingerprint();
+                }
+                Err(_) => prop_assert!(!resolved),
+            }
         }
     }
 }
@@ -1,6 +1,9 @@
 use std::collections::HashSet;
 
-use bitcoin::{ecdsa::Signature as EcdsaSignature, Amount, OutPoint, PublicKey, Transaction};
+use bitcoin::{
+    ecdsa::Signature as EcdsaSignature, hashes::Hash, secp256k1, Amount, OutPoint, PublicKey,
+    Transaction,
+};
 
 use crate::{
     util::{extract_all_signatures, OutputType},
@@ -55,18 +58,23 @@ pub(crate) fn get_input_order(
     let orignial_prevout = tx.input.clone();
     let mut sorted_prevouts = tx.input.clone();
     sorted_prevouts.sort_by(|a, b| {
-        let txid1 = hex::decode(a.previous_output.txid.to_string()).unwrap();
-        let txid2 = hex::decode(b.previous_output.txid.to_string()).unwrap();
+        // BIP 69 compares txids in their displayed (reversed) byte order
+        let txid1 = a.previous_output.txid.to_byte_array();
+        let txid2 = b.previous_output.txid.to_byte_array();
         txid1
-            .cmp(&txid2)
+            .iter()
+            .rev()
+            .cmp(txid2.iter().rev())
             .then_with(|| a.previous_output.vout.cmp(&b.previous_output.vout))
     });
     if orignial_prevout == sorted_prevouts {
         sorting_types.push(InputSortingType::Bip69);
     }
 
-    // Note: Historical sorting would require access to confirmation height data
-    // which isn't available yet in this API
+    if inputs_oldest_first(tx, prev_outs) {
+        sorting_types.push(InputSortingType::Historical);
+    }
+
     if sorting_types.is_empty() {
         sorting_types.push(InputSortingType::Unknown);
     }
@@ -74,20 +82,108 @@ pub(crate) fn get_input_order(
     sorting_types
 }
 
-/// Returns true if the transaction has low-order R-grinding signatures
+/// Whether the inputs spend coins in confirmation order, oldest first.
+/// Only known if the confirmation height of every input is, and inputs confirmed in a single block
+/// carry no ordering.
+pub(crate) fn inputs_oldest_first(tx: &Transaction, prev_outs: &[TxOutWithOutpoint]) -> bool {
+    let Some(heights) = tx
+        .input
+        .iter()
+        .map(|input| {
+            prev_outs
+                .iter()
+                .find(|prevout| prevout.outpoint == input.previous_output)
+                .and_then(|prevout| prevout.height)
+        })
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+    heights.windows(2).all(|w| w[0] <= w[1]) && heights.first() < heights.last()
+}
+
+/// Returns true if all ECDSA signatures of the transaction have low order R values.
+/// Schnorr signatures are fixed size, so there is nothing to grind, and a transaction without
+/// ECDSA signatures is low R like in the reference implementation.
 /// https://bitcoinops.org/en/topics/low-r-grinding
 pub(crate) fn low_order_r_grinding(tx: &Transaction) -> bool {
-    let sigs = extract_all_signatures(tx);
-    for sig_bytes in sigs.iter() {
-        // TODO need to deal with compact schnorr sigs
-        let sig = EcdsaSignature::from_slice(sig_bytes).unwrap();
-        let compact = sig.to_vec();
-        if compact[0] < 0x80 {
-            return true;
+    low_order_r_signatures(&extract_all_signatures(tx))
+}
+
+/// Like [`low_order_r_grinding`], given the serialized signatures
+pub(crate) fn low_order_r_signatures(signatures: &[Vec<u8>]) -> bool {
+    let ecdsa_sigs = signatures
+        .iter()
+        .filter_map(|sig_bytes| {
+            // The last byte is the sighash type, which may be non-standard
+            let (_, der) = sig_bytes.split_last()?;
+            secp256k1::ecdsa::Signature::from_der(der).ok()
+        })
+        .collect::<Vec<_>>();
+
+    // R needs 33 bytes in DER if its high bit is set
+    ecdsa_sigs
+        .iter()
+        .all(|sig| sig.serialize_compact()[0] < 0x80)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Enum))]
+pub enum SighashType {
+    /// Taproot default, commits to everything without a sighash byte
+    Default,
+    /// SIGHASH_ALL
+    All,
+    /// SIGHASH_NONE
+    None,
+    /// SIGHASH_SINGLE
+    Single,
+    /// SIGHASH_ALL | SIGHASH_ANYONECANPAY
+    AllPlusAnyoneCanPay,
+    /// SIGHASH_NONE | SIGHASH_ANYONECANPAY
+    NonePlusAnyoneCanPay,
+    /// SIGHASH_SINGLE | SIGHASH_ANYONECANPAY
+    SinglePlusAnyoneCanPay,
+    /// Any other sighash byte
+    Unknown,
+}
+
+impl From<u8> for SighashType {
+    fn from(byte: u8) -> Self {
+        match byte {
+            0x00 => SighashType::Default,
+            0x01 => SighashType::All,
+            0x02 => SighashType::None,
+            0x03 => SighashType::Single,
+            0x81 => SighashType::AllPlusAnyoneCanPay,
+            0x82 => SighashType::NonePlusAnyoneCanPay,
+            0x83 => SighashType::SinglePlusAnyoneCanPay,
+            _ => SighashType::Unknown,
         }
     }
+}
 
-    false
+/// Returns the distinct sighash types used by the signatures in the transaction, sorted
+pub(crate) fn get_sighash_types(tx: &Transaction) -> Vec<SighashType> {
+    signature_sighash_types(&extract_all_signatures(tx))
+}
+
+/// Like [`get_sighash_types`], given the serialized signatures
+pub(crate) fn signature_sighash_types(signatures: &[Vec<u8>]) -> Vec<SighashType> {
+    let mut sighash_types = signatures
+        .iter()
+        .map(|sig| {
+            // ECDSA sigs always end in a sighash byte, schnorr sigs only if it is not the default
+            if EcdsaSignature::from_slice(sig).is_ok() || sig.len() == 65 {
+                SighashType::from(sig[sig.len() - 1])
+            } else {
+                SighashType::Default
+            }
+        })
+        .collect::<Vec<_>>();
+    sighash_types.sort();
+    sighash_types.dedup();
+    sighash_types
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -101,7 +197,8 @@ pub enum InputSortingType {
     Descending,
     /// Inputs are sorted according to BIP 69
     Bip69,
-    // TODO: current unused. If we have confirmation height on input, we can use this
+    /// Inputs are sorted by the confirmation height of the outputs they spend,
+    /// only detected when the heights are known and differ
     Historical,
     /// Input sorting type is unknown
     Unknown,
@@ -151,6 +248,31 @@ pub(crate) fn spending_spk_has_uncompressed_pubkey(
     false
 }
 
+/// Returns true if a P2PKH input reveals an uncompressed pubkey in its scriptSig
+pub(crate) fn scriptsig_has_uncompressed_pubkey(
+    spending_tx: &Transaction,
+    prev_outs: &[TxOutWithOutpoint],
+) -> bool {
+    spending_tx
+        .input
+        .iter()
+        .zip(prev_outs)
+        .any(|(input, prev_out)| {
+            if !prev_out.txout.script_pubkey.is_p2pkh() {
+                return false;
+            }
+            // The pubkey is the last push of the scriptSig
+            input
+                .script_sig
+                .instructions()
+                .last()
+                .and_then(|instruction| {
+                    PublicKey::from_slice(instruction.ok()?.push_bytes()?.as_bytes()).ok()
+                })
+                .is_some_and(|pubkey| !pubkey.compressed)
+        })
+}
+
 // TODO: this isnt used or exported. Is this a viable fingerprint?
 #[allow(unused)]
 pub(crate) fn spending_witness_has_uncompressed_pubkey(spending_tx: &Transaction) -> bool {
@@ -179,187 +301,58 @@ pub(crate) fn spending_witness_has_uncompressed_pubkey(spending_tx: &Transaction
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::util::TxOutWithOutpoint;
-    use bitcoin::{ScriptBuf, TxIn, TxOut};
-    use std::str::FromStr;
-    // TODO: need a test harness/util for creating transactions and prevouts
-
-    fn create_p2pk_script(compressed: bool) -> ScriptBuf {
-        // Create a public key (compressed or uncompressed)
-        let secp = bitcoin::secp256k1::Secp256k1::new();
-        let mut rng = rand::thread_rng();
-        let (secret_key, _) = secp.generate_keypair(&mut rng);
-        let pubkey = PublicKey::new(secret_key.public_key(&secp));
-
-        // Force uncompressed if needed
-        let pubkey_bytes = if compressed {
-            pubkey.to_bytes()
-        } else {
-            // Create uncompressed pubkey (starts with 0x04)
-            let mut uncompressed = vec![];
-            uncompressed.extend_from_slice(&pubkey.inner.serialize_uncompressed());
-            PublicKey::from_slice(&uncompressed).unwrap().to_bytes()
-        };
-
-        let pubkey = PublicKey::from_slice(&pubkey_bytes).unwrap();
-        assert_eq!(pubkey.compressed, compressed);
-        let script = ScriptBuf::new_p2pk(&pubkey);
-        script
+    use crate::{
+        test_utils::{ScriptType, TxBuilder, TxOrdering},
+        util::get_prevouts,
+    };
+    use proptest::{collection::vec, prelude::*};
+
+    fn spend(
+        seed: u64,
+        script_type: ScriptType,
+        compressed: bool,
+    ) -> (Transaction, Vec<Transaction>) {
+        TxBuilder::new(seed)
+            .compressed_keys(compressed)
+            .input(Amount::from_sat(1000), script_type)
+            .build()
     }
 
     #[test]
     fn test_spending_spk_has_uncompressed_pubkey_with_compressed_p2pk() {
-        // Create a P2PK script with compressed pubkey
-        let spk = create_p2pk_script(true);
-        let outpoint = OutPoint::from_str(
-            "0000000000000000000000000000000000000000000000000000000000000000:0",
-        )
-        .unwrap();
-
-        let prev_out = TxOutWithOutpoint {
-            txout: TxOut {
-                value: bitcoin::Amount::from_sat(1000),
-                script_pubkey: spk,
-            },
-            outpoint,
-        };
+        let (spending_tx, prev_txs) = spend(0, ScriptType::P2pk, true);
+        let prev_outs = get_prevouts(&spending_tx, &prev_txs).unwrap();
 
-        let spending_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: bitcoin::locktime::absolute::LockTime::ZERO,
-            input: vec![TxIn {
-                previous_output: outpoint,
-                script_sig: ScriptBuf::new(),
-                sequence: bitcoin::Sequence::MAX,
-                witness: bitcoin::Witness::new(),
-            }],
-            output: vec![],
-        };
-
-        let result = spending_spk_has_uncompressed_pubkey(&spending_tx, &[prev_out]);
+        let result = spending_spk_has_uncompressed_pubkey(&spending_tx, &prev_outs);
         assert_eq!(result, false, "false for compressed pubkey");
     }
 
     #[test]
     fn test_spending_spk_has_uncompressed_pubkey_with_uncompressed_p2pk() {
-        // Create a P2PK script with uncompressed pubkey
-        let spk = create_p2pk_script(false);
-        let outpoint = OutPoint::from_str(
-            "0000000000000000000000000000000000000000000000000000000000000000:0",
-        )
-        .unwrap();
-
-        let prev_out = TxOutWithOutpoint {
-            txout: TxOut {
-                value: bitcoin::Amount::from_sat(1000),
-                script_pubkey: spk,
-            },
-            outpoint,
-        };
-
-        let spending_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: bitcoin::locktime::absolute::LockTime::ZERO,
-            input: vec![TxIn {
-                previous_output: outpoint,
-                script_sig: ScriptBuf::new(),
-                sequence: bitcoin::Sequence::MAX,
-                witness: bitcoin::Witness::new(),
-            }],
-            output: vec![],
-        };
+        let (spending_tx, prev_txs) = spend(0, ScriptType::P2pk, false);
+        let prev_outs = get_prevouts(&spending_tx, &prev_txs).unwrap();
 
-        let result = spending_spk_has_uncompressed_pubkey(&spending_tx, &[prev_out]);
+        let result = spending_spk_has_uncompressed_pubkey(&spending_tx, &prev_outs);
         assert_eq!(result, true, "true for uncompressed pubkey");
     }
 
     #[test]
     fn test_spending_spk_has_uncompressed_pubkey_with_non_p2pk() {
-        let address = bitcoin::Address::from_str("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa")
-            .unwrap()
-            .require_network(bitcoin::Network::Bitcoin)
-            .unwrap();
-        let spk = address.script_pubkey();
-        let outpoint = OutPoint::from_str(
-            "0000000000000000000000000000000000000000000000000000000000000000:0",
-        )
-        .unwrap();
-
-        let prev_out = TxOutWithOutpoint {
-            txout: TxOut {
-                value: bitcoin::Amount::from_sat(1000),
-                script_pubkey: spk,
-            },
-            outpoint,
-        };
+        let (spending_tx, prev_txs) = spend(0, ScriptType::P2pkh, true);
+        let prev_outs = get_prevouts(&spending_tx, &prev_txs).unwrap();
 
-        let spending_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: bitcoin::locktime::absolute::LockTime::ZERO,
-            input: vec![TxIn {
-                previous_output: outpoint,
-                script_sig: ScriptBuf::new(),
-                sequence: bitcoin::Sequence::MAX,
-                witness: bitcoin::Witness::new(),
-            }],
-            output: vec![],
-        };
-
-        let result = spending_spk_has_uncompressed_pubkey(&spending_tx, &[prev_out]);
+        let result = spending_spk_has_uncompressed_pubkey(&spending_tx, &prev_outs);
         assert_eq!(result, false, "Should return false for non-P2PK scripts");
     }
 
     #[test]
     fn test_spending_spk_has_uncompressed_pubkey_with_multiple_inputs() {
-        let spk1 = create_p2pk_script(true); // compressed
-        let spk2 = create_p2pk_script(false); // uncompressed
-
-        let outpoint1 = OutPoint::from_str(
-            "0000000000000000000000000000000000000000000000000000000000000001:0",
-        )
-        .unwrap();
-        let outpoint2 = OutPoint::from_str(
-            "0000000000000000000000000000000000000000000000000000000000000002:0",
-        )
-        .unwrap();
-
-        let prev_outs = vec![
-            TxOutWithOutpoint {
-                txout: TxOut {
-                    value: bitcoin::Amount::from_sat(1000),
-                    script_pubkey: spk1,
-                },
-                outpoint: outpoint1,
-            },
-            TxOutWithOutpoint {
-                txout: TxOut {
-                    value: bitcoin::Amount::from_sat(2000),
-                    script_pubkey: spk2,
-                },
-                outpoint: outpoint2,
-            },
-        ];
-
-        // Spending tx with first input being compressed P2PK
-        let spending_tx = Transaction {
-            version: bitcoin::transaction::Version::TWO,
-            lock_time: bitcoin::locktime::absolute::LockTime::ZERO,
-            input: vec![
-                TxIn {
-                    previous_output: outpoint1,
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::Sequence::MAX,
-                    witness: bitcoin::Witness::new(),
-                },
-                TxIn {
-                    previous_output: outpoint2,
-                    script_sig: ScriptBuf::new(),
-                    sequence: bitcoin::Sequence::MAX,
-                    witness: bitcoin::Witness::new(),
-                },
-            ],
-            output: vec![],
-        };
+        // First input is compressed, second is uncompressed
+        let (mut spending_tx, mut prev_txs) = spend(1, ScriptType::P2pk, true);
+        let (uncompressed_tx, uncompressed_prev_txs) = spend(2, ScriptType::P2pk, false);
+        spending_tx.input.extend(uncompressed_tx.input);
+        prev_txs.extend(uncompressed_prev_txs);
+        let prev_outs = get_prevouts(&spending_tx, &prev_txs).unwrap();
 
         // Should return true because first input has compressed P2PK
         let result = spending_spk_has_uncompressed_pubkey(&spending_tx, &prev_outs);
@@ -378,4 +371,38 @@ mod tests {
         let result = spending_spk_has_uncompressed_pubkey(&spending_tx, &[]);
         assert_eq!(result, false, "false for empty inputs");
     }
+
+    #[test]
+    fn test_historical_input_order() {
+        let (tx, prev_txs) = TxBuilder::new(3)
+            .input(Amount::from_sat(5_000), ScriptType::P2wpkh)
+            .input(Amount::from_sat(1_000), ScriptType::P2wpkh)
+            .build();
+        let mut prev_outs = get_prevouts(&tx, &prev_txs).unwrap();
+        assert!(!get_input_order(&tx, &prev_outs).contains(&InputSortingType::Historical));
+
+        prev_outs[0].height = Some(100);
+        prev_outs[1].height = Some(200);
+        assert!(get_input_order(&tx, &prev_outs).contains(&InputSortingType::Historical));
+
+        prev_outs[1].height = Some(50);
+        assert!(!get_input_order(&tx, &prev_outs).contains(&InputSortingType::Historical));
+
+        // Inputs from the same block carry no ordering
+        prev_outs[1].height = Some(100);
+        assert!(!get_input_order(&tx, &prev_outs).contains(&InputSortingType::Historical));
+    }
+
+    proptest! {
+        #[test]
+        fn test_bip69_sorted_inputs_report_bip69(seed: u64, values in vec(1_000u64..1_000_000, 2..6)) {
+            let builder = values.iter().fold(
+                TxBuilder::new(seed).input_ordering(TxOrdering::Bip69),
+                |builder, value| builder.input(Amount::from_sat(*value), ScriptType::P2wpkh),
+            );
+            let (tx, prev_txs) = builder.build();
+            let order = get_input_order(&tx, &get_prevouts(&tx, &prev_txs).unwrap());
+            prop_assert!(order.contains(&InputSortingType::Bip69), "{:?}", order);
+        }
+    }
 }
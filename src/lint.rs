@@ -0,0 +1,328 @@
+//! Privacy lints: actionable warnings about the fingerprints a transaction leaks.
+//! Lints only use heuristics that do not need signatures, so a PSBT can be checked before signing
+//! with [`Heuristics::from_psbt`].
+
+use std::{cmp::Reverse, collections::BTreeSet};
+
+use crate::{
+    heuristics::Heuristics, input::InputSortingType, output::ChangeIndex, util::OutputType,
+};
+
+/// Orderings are only meaningful with this many inputs, fewer are sorted by chance too often
+const MIN_ORDERED_INPUTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Enum))]
+pub enum Severity {
+    /// Narrows down the wallet that made the transaction
+    Low,
+    /// Helps tell the change apart from the payment
+    Medium,
+    /// Links coins of the user to each other
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Enum))]
+pub enum Lint {
+    /// An output pays to the same script as an input
+    AddressReuse,
+    /// The change output has another script type than the payment
+    ChangeTypeMismatch,
+    /// The inputs spend outputs of different script types
+    MixedInputTypes,
+    /// An input spends an output with an uncompressed public key
+    UncompressedPubkey,
+    /// The inputs are sorted in a deterministic order other than BIP 69
+    InputOrdering,
+}
+
+/// A fingerprint the transaction leaks, and how to avoid it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct Finding {
+    pub lint: Lint,
+    pub severity: Severity,
+    /// What the transaction reveals
+    pub message: String,
+    /// How to avoid revealing it
+    pub remediation: String,
+}
+
+fn join_types<'a>(types: impl IntoIterator<Item = &'a OutputType>) -> String {
+    types
+        .into_iter()
+        .map(|output_type| output_type.to_string())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn change_type_mismatch(heuristics: &Heuristics) -> Option<Finding> {
+    let ChangeIndex::Found(change) = heuristics.change_index else {
+        return None;
+    };
+    let change_type = heuristics.output_types.get(change)?;
+    let payment_types = heuristics
+        .output_types
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != change)
+        .map(|(_, output_type)| output_type)
+        .collect::<Vec<_>>();
+    if payment_types.is_empty() || payment_types.iter().all(|t| *t == change_type) {
+        return None;
+    }
+    Some(Finding {
+        lint: Lint::ChangeTypeMismatch,
+        severity: Severity::Medium,
+        message: format!(
+            "The change output is {} while the payment is {}",
+            change_type,
+            join_types(payment_types)
+        ),
+        remediation: "Send the change to the script type of the payment".to_string(),
+    })
+}
+
+fn input_ordering(heuristics: &Heuristics) -> Option<Finding> {
+    let input_order = &heuristics.input_order;
+    if heuristics.input_types.len() < MIN_ORDERED_INPUTS
+        || input_order.contains(&InputSortingType::Bip69)
+    {
+        return None;
+    }
+    let ordering = if input_order.contains(&InputSortingType::Historical) {
+        "confirmation height"
+    } else if input_order.contains(&InputSortingType::Ascending)
+        || input_order.contains(&InputSortingType::Descending)
+    {
+        "amount"
+    } else {
+        return None;
+    };
+    Some(Finding {
+        lint: Lint::InputOrdering,
+        severity: Severity::Low,
+        message: format!(
+            "The inputs are sorted by {}, which few wallets do",
+            ordering
+        ),
+        remediation: "Shuffle the inputs, or sort them according to BIP 69".to_string(),
+    })
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl Heuristics {
+    /// Returns the fingerprints the transaction leaks, most severe first
+    pub fn lint(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        if self.address_reuse {
+            findings.push(Finding {
+                lint: Lint::AddressReuse,
+                severity: Severity::High,
+                message: "An output pays to an address spent by an input".to_string(),
+                remediation: "Send change and payments to fresh addresses".to_string(),
+            });
+        }
+        findings.extend(change_type_mismatch(self));
+        if self.mixed_input_types {
+            findings.push(Finding {
+                lint: Lint::MixedInputTypes,
+                severity: Severity::Medium,
+                message: format!(
+                    "The inputs spend {} outputs together",
+                    join_types(&self.input_types)
+                ),
+                remediation: "Spend coins of one script type per transaction, \
+                    and migrate older coins in a transaction of their own"
+                    .to_string(),
+            });
+        }
+        if self.spending_spk_has_uncompressed_pubkey || self.reveals_uncompressed_pubkey {
+            findings.push(Finding {
+                lint: Lint::UncompressedPubkey,
+                severity: Severity::Medium,
+                message: "An input spends an output with an uncompressed public key".to_string(),
+                remediation: "Move the coins to a wallet using compressed or segwit keys"
+                    .to_string(),
+            });
+        }
+        findings.extend(input_ordering(self));
+
+        findings.sort_by_key(|finding| Reverse(finding.severity));
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        change::ChangeHints,
+        detect_wallet,
+        psbt::psbt_transaction,
+        test_utils::{ScriptType, TxBuilder, TxOrdering},
+        util::get_prevouts,
+    };
+    use bitcoin::{Amount, Psbt, PublicKey, ScriptBuf, Transaction, Witness};
+
+    fn heuristics((tx, prev_txs): (Transaction, Vec<Transaction>)) -> Heuristics {
+        Heuristics::from_prevouts(
+            &tx,
+            &get_prevouts(&tx, &prev_txs).unwrap(),
+            &ChangeHints::default(),
+        )
+    }
+
+    fn lints(heuristics: &Heuristics) -> Vec<Lint> {
+        heuristics
+            .lint()
+            .into_iter()
+            .map(|finding| finding.lint)
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_transaction() {
+        let clean = heuristics(
+            TxBuilder::new(0)
+                .input(Amount::from_sat(100_000), ScriptType::P2tr)
+                .output(Amount::from_sat(40_000), ScriptType::P2tr)
+                .change(Amount::from_sat(59_000), ScriptType::P2tr)
+                .build(),
+        );
+        assert_eq!(clean.lint(), vec![]);
+    }
+
+    #[test]
+    fn test_lints() {
+        let leaky = heuristics(
+            TxBuilder::new(1)
+                .input(Amount::from_sat(100_000), ScriptType::P2pkh)
+                .input(Amount::from_sat(50_000), ScriptType::P2pk)
+                .output(Amount::from_sat(120_000), ScriptType::P2tr)
+                .change(Amount::from_sat(29_000), ScriptType::P2pkh)
+                .change_reuses_input_address(true)
+                .compressed_keys(false)
+                .build(),
+        );
+        let findings = leaky.lint();
+        assert_eq!(
+            lints(&leaky),
+            vec![
+                Lint::AddressReuse,
+                Lint::ChangeTypeMismatch,
+                Lint::MixedInputTypes,
+                Lint::UncompressedPubkey,
+            ]
+        );
+        assert!(findings
+            .windows(2)
+            .all(|pair| pair[0].severity >= pair[1].severity));
+        assert!(findings
+            .iter()
+            .all(|finding| !finding.message.is_empty() && !finding.remediation.is_empty()));
+
+        // Uncompressed P2PKH keys are only revealed by the scriptSig
+        let legacy = |compressed| {
+            TxBuilder::new(3)
+                .input(Amount::from_sat(100_000), ScriptType::P2pkh)
+                .output(Amount::from_sat(60_000), ScriptType::P2pkh)
+                .change(Amount::from_sat(39_000), ScriptType::P2pkh)
+                .compressed_keys(compressed)
+                .build()
+        };
+        let (tx, prev_txs) = legacy(false);
+        assert_eq!(
+            lints(&heuristics(legacy(false))),
+            vec![Lint::UncompressedPubkey]
+        );
+        // Wallet detection only looks at P2PK keys, like the reference implementation
+        let (compressed_tx, compressed_prev_txs) = legacy(true);
+        assert_eq!(
+            detect_wallet(&tx, &prev_txs).unwrap().0,
+            detect_wallet(&compressed_tx, &compressed_prev_txs)
+                .unwrap()
+                .0
+        );
+    }
+
+    #[test]
+    fn test_input_ordering() {
+        let builder = |ordering| {
+            TxBuilder::new(2)
+                .input(Amount::from_sat(10_000), ScriptType::P2wpkh)
+                .input(Amount::from_sat(20_000), ScriptType::P2wpkh)
+                .input(Amount::from_sat(30_000), ScriptType::P2wpkh)
+                .output(Amount::from_sat(50_000), ScriptType::P2wpkh)
+                .change(Amount::from_sat(9_000), ScriptType::P2wpkh)
+                .input_ordering(ordering)
+        };
+        let ascending = heuristics(builder(TxOrdering::Unsorted).build());
+        assert_eq!(lints(&ascending), vec![Lint::InputOrdering]);
+        let bip69 = heuristics(builder(TxOrdering::Bip69).build());
+        assert!(!lints(&bip69).contains(&Lint::InputOrdering));
+    }
+
+    fn unsigned_psbt(tx: &Transaction, prev_txs: &[Transaction]) -> Psbt {
+        let mut unsigned = tx.clone();
+        for txin in unsigned.input.iter_mut() {
+            txin.script_sig = ScriptBuf::new();
+            txin.witness = Witness::new();
+        }
+        let mut psbt = Psbt::from_unsigned_tx(unsigned).unwrap();
+        for (input, txin) in psbt.inputs.iter_mut().zip(tx.input.iter()) {
+            input.non_witness_utxo = prev_txs
+                .iter()
+                .find(|prev_tx| prev_tx.compute_txid() == txin.previous_output.txid)
+                .cloned();
+        }
+        psbt
+    }
+
+    #[test]
+    fn test_lint_psbt() {
+        let (tx, prev_txs) = TxBuilder::new(1)
+            .input(Amount::from_sat(100_000), ScriptType::P2pkh)
+            .input(Amount::from_sat(50_000), ScriptType::P2wpkh)
+            .output(Amount::from_sat(120_000), ScriptType::P2tr)
+            .change(Amount::from_sat(29_000), ScriptType::P2pkh)
+            .build();
+        let psbt = unsigned_psbt(&tx, &prev_txs);
+        assert_eq!(psbt_transaction(&psbt), psbt.unsigned_tx);
+
+        let from_psbt = Heuristics::from_psbt(&psbt).unwrap();
+        assert_eq!(from_psbt.lint(), heuristics((tx, prev_txs)).lint());
+    }
+
+    #[test]
+    fn test_lint_psbt_uncompressed_p2pkh() {
+        let (tx, prev_txs) = TxBuilder::new(3)
+            .input(Amount::from_sat(100_000), ScriptType::P2pkh)
+            .output(Amount::from_sat(60_000), ScriptType::P2pkh)
+            .change(Amount::from_sat(39_000), ScriptType::P2pkh)
+            .compressed_keys(false)
+            .build();
+        let pubkey = tx.input[0]
+            .script_sig
+            .instructions()
+            .last()
+            .and_then(|instruction| {
+                PublicKey::from_slice(instruction.ok()?.push_bytes()?.as_bytes()).ok()
+            })
+            .unwrap();
+        let mut psbt = unsigned_psbt(&tx, &prev_txs);
+        // Nothing in the unsigned transaction reveals the pubkey
+        assert!(!lints(&Heuristics::from_psbt(&psbt).unwrap()).contains(&Lint::UncompressedPubkey));
+
+        psbt.inputs[0]
+            .bip32_derivation
+            .insert(pubkey.inner, Default::default());
+        assert_eq!(
+            lints(&Heuristics::from_psbt(&psbt).unwrap()),
+            vec![Lint::UncompressedPubkey]
+        );
+    }
+}
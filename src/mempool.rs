@@ -0,0 +1,260 @@
+//! Reading the `mempool.dat` snapshot Bitcoin Core writes on shutdown, so the mempool can be
+//! fingerprinted offline.
+//!
+//! The file starts with a version. Version 2, written since Bitcoin Core 28, is followed by an
+//! 8 byte XOR key obfuscating the rest of the file. Then come the transactions, each with the
+//! time it was first seen and its fee delta, followed by the fee deltas of transactions that are
+//! not in the mempool and the unbroadcast transaction ids.
+
+use std::{fs, path::Path};
+
+use bitcoin::{
+    consensus::{encode::VarInt, Decodable},
+    Transaction, Txid,
+};
+
+use crate::{
+    batch::{analyze_transactions, Analysis},
+    prevouts::{Overlay, PrevoutLookup},
+};
+
+const VERSION_NO_XOR_KEY: u64 = 1;
+const VERSION: u64 = 2;
+
+/// A transaction of the mempool snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MempoolEntry {
+    pub tx: Transaction,
+    /// Unix time the transaction entered the mempool
+    pub first_seen: i64,
+    /// Fee added with `prioritisetransaction`, in satoshis
+    pub fee_delta: i64,
+}
+
+/// The contents of a `mempool.dat` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MempoolSnapshot {
+    pub entries: Vec<MempoolEntry>,
+    /// Transactions submitted locally that were not yet relayed
+    pub unbroadcast: Vec<Txid>,
+}
+
+/// Reads a `mempool.dat` file
+pub fn read_mempool(path: &Path) -> Result<MempoolSnapshot, String> {
+    let data = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_mempool(data).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Parses the contents of a `mempool.dat` file
+pub fn parse_mempool(mut data: Vec<u8>) -> Result<MempoolSnapshot, String> {
+    fn decode<T: Decodable>(reader: &mut &[u8], what: &str) -> Result<T, String> {
+        T::consensus_decode(reader).map_err(|e| format!("invalid {}: {}", what, e))
+    }
+
+    let mut reader = data.as_slice();
+    let version: u64 = decode(&mut reader, "version")?;
+    let xor_key: Vec<u8> = match version {
+        VERSION_NO_XOR_KEY => vec![0],
+        VERSION => decode(&mut reader, "xor key")?,
+        _ => return Err(format!("unsupported version {}", version)),
+    };
+    if xor_key.is_empty() {
+        return Err("empty xor key".to_string());
+    }
+
+    // The key applies from the start of the file, but only what follows it is obfuscated
+    let start = data.len() - reader.len();
+    for (i, byte) in data.iter_mut().enumerate().skip(start) {
+        *byte ^= xor_key[i % xor_key.len()];
+    }
+
+    let mut reader = &data[start..];
+    let count: u64 = decode(&mut reader, "transaction count")?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        entries.push(MempoolEntry {
+            tx: decode(&mut reader, "transaction")?,
+            first_seen: decode(&mut reader, "time")?,
+            fee_delta: decode(&mut reader, "fee delta")?,
+        });
+    }
+
+    // Fee deltas of transactions not in the mempool
+    let deltas: VarInt = decode(&mut reader, "fee delta count")?;
+    for _ in 0..deltas.0 {
+        decode::<Txid>(&mut reader, "fee delta txid")?;
+        decode::<i64>(&mut reader, "fee delta")?;
+    }
+
+    let unbroadcast: VarInt = decode(&mut reader, "unbroadcast count")?;
+    let unbroadcast = (0..unbroadcast.0)
+        .map(|_| decode(&mut reader, "unbroadcast txid"))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(MempoolSnapshot {
+        entries,
+        unbroadcast,
+    })
+}
+
+/// The analysis of a mempool transaction
+#[derive(Debug)]
+pub struct MempoolAnalysis {
+    pub txid: Txid,
+    /// Unix time the transaction entered the mempool
+    pub first_seen: i64,
+    pub analysis: Result<Analysis, String>,
+}
+
+/// Analyses every transaction of the snapshot, in snapshot order.
+/// Outputs of unconfirmed parents are resolved from the snapshot itself,
+/// other previous outputs from `lookup`.
+pub fn analyze_mempool<L: PrevoutLookup + Sync + ?Sized>(
+    snapshot: &MempoolSnapshot,
+    lookup: &L,
+) -> Vec<MempoolAnalysis> {
+    let mut overlay = Overlay::new(lookup);
+    for entry in snapshot.entries.iter() {
+        overlay.add_outputs(&entry.tx, None);
+    }
+
+    let results = analyze_transactions(snapshot.entries.iter().map(|entry| &entry.tx), &overlay);
+    snapshot
+        .entries
+        .iter()
+        .zip(results)
+        .map(|(entry, analysis)| MempoolAnalysis {
+            txid: entry.tx.compute_txid(),
+            first_seen: entry.first_seen,
+            analysis,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{ScriptType, TxBuilder};
+    use bitcoin::{
+        consensus::{serialize, Encodable},
+        Amount, OutPoint, ScriptBuf, Sequence, TxIn, Witness,
+    };
+
+    /// Writes a mempool.dat the way Bitcoin Core does
+    fn mempool_dat(
+        entries: &[MempoolEntry],
+        unbroadcast: &[Txid],
+        xor_key: Option<[u8; 8]>,
+    ) -> Vec<u8> {
+        let mut data = Vec::new();
+        match xor_key {
+            Some(key) => {
+                VERSION.consensus_encode(&mut data).unwrap();
+                key.to_vec().consensus_encode(&mut data).unwrap();
+            }
+            None => {
+                VERSION_NO_XOR_KEY.consensus_encode(&mut data).unwrap();
+            }
+        }
+        let start = data.len();
+
+        (entries.len() as u64).consensus_encode(&mut data).unwrap();
+        for entry in entries {
+            data.extend(serialize(&entry.tx));
+            entry.first_seen.consensus_encode(&mut data).unwrap();
+            entry.fee_delta.consensus_encode(&mut data).unwrap();
+        }
+        // A fee delta for a transaction that is not in the mempool
+        VarInt(1).consensus_encode(&mut data).unwrap();
+        Txid::from_raw_hash(bitcoin::hashes::Hash::all_zeros())
+            .consensus_encode(&mut data)
+            .unwrap();
+        1_000i64.consensus_encode(&mut data).unwrap();
+        VarInt(unbroadcast.len() as u64)
+            .consensus_encode(&mut data)
+            .unwrap();
+        for txid in unbroadcast {
+            txid.consensus_encode(&mut data).unwrap();
+        }
+
+        if let Some(key) = xor_key {
+            for (i, byte) in data.iter_mut().enumerate().skip(start) {
+                *byte ^= key[i % 8];
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_parse_mempool() {
+        let (parent, prev_txs) = TxBuilder::new(0)
+            .input(Amount::from_sat(100_000), ScriptType::P2wpkh)
+            .output(Amount::from_sat(30_000), ScriptType::P2pkh)
+            .change(Amount::from_sat(69_000), ScriptType::P2wpkh)
+            .build();
+        // Spends the change of its unconfirmed parent
+        let child = Transaction {
+            input: vec![TxIn {
+                previous_output: OutPoint::new(parent.compute_txid(), 1),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            }],
+            ..parent.clone()
+        };
+        let (orphan, _) = TxBuilder::new(1)
+            .input(Amount::from_sat(5_000), ScriptType::P2tr)
+            .build();
+        let entries = vec![
+            MempoolEntry {
+                tx: parent.clone(),
+                first_seen: 1_700_000_000,
+                fee_delta: 0,
+            },
+            MempoolEntry {
+                tx: child.clone(),
+                first_seen: 1_700_000_060,
+                fee_delta: -500,
+            },
+            MempoolEntry {
+                tx: orphan.clone(),
+                first_seen: 1_700_000_120,
+                fee_delta: 0,
+            },
+        ];
+        let unbroadcast = vec![child.compute_txid()];
+
+        let xor_key = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+        let snapshot = parse_mempool(mempool_dat(&entries, &unbroadcast, Some(xor_key))).unwrap();
+        assert_eq!(snapshot.entries, entries);
+        assert_eq!(snapshot.unbroadcast, unbroadcast);
+        let unobfuscated = parse_mempool(mempool_dat(&entries, &unbroadcast, None)).unwrap();
+        assert_eq!(unobfuscated, snapshot);
+
+        let results = analyze_mempool(&snapshot, prev_txs.as_slice());
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].txid, parent.compute_txid());
+        assert_eq!(results[1].first_seen, 1_700_000_060);
+        assert!(results[0].analysis.is_ok());
+        assert!(results[1].analysis.is_ok());
+        assert!(results[2]
+            .analysis
+            .as_ref()
+            .unwrap_err()
+            .contains("unknown previous output"));
+    }
+
+    #[test]
+    fn test_parse_invalid_mempool() {
+        let mut data = Vec::new();
+        3u64.consensus_encode(&mut data).unwrap();
+        assert_eq!(parse_mempool(data).unwrap_err(), "unsupported version 3");
+
+        let mut data = Vec::new();
+        VERSION_NO_XOR_KEY.consensus_encode(&mut data).unwrap();
+        5u64.consensus_encode(&mut data).unwrap();
+        assert!(parse_mempool(data)
+            .unwrap_err()
+            .starts_with("invalid transaction"));
+    }
+}
@@ -1,8 +1,10 @@
 use std::collections::HashSet;
 
-use bitcoin::Transaction;
+use bitcoin::{Amount, Transaction};
 
 use crate::{
+    change::{detect_change, ChangeHints},
+    fee::get_fee,
     input::get_input_types,
     util::{get_output_type, OutputType, TxOutWithOutpoint},
 };
@@ -29,67 +31,82 @@ impl ChangeIndex {
 
 /// Attempts to identify the change output in a transaction using various heuristics
 pub(crate) fn get_change_index(tx: &Transaction, prev_outs: &[TxOutWithOutpoint]) -> ChangeIndex {
-    // Single output case
+    detect_change(tx, prev_outs, &ChangeHints::default()).change_index()
+}
+
+/// Reduces a set of change candidates to a change index, only concluding on a single candidate
+fn change_index_from_candidates(tx: &Transaction, candidates: &[usize]) -> ChangeIndex {
     if tx.output.len() == 1 {
         return ChangeIndex::NoChange;
     }
-
-    // Get input address types
-    let input_types = get_input_types(tx, prev_outs);
-    // Get output address types
-    let output_types = get_output_types(tx);
-
-    // Check if all inputs are same type and exactly one output matches
-    if input_types.iter().all(|t| *t == input_types[0]) {
-        let matching = output_types
-            .iter()
-            .enumerate()
-            .filter(|(_, t)| **t == input_types[0])
-            .map(|(i, _)| i)
-            .collect::<Vec<_>>();
-
-        if matching.len() == 1 {
-            return ChangeIndex::Found(matching[0]);
-        }
+    match candidates {
+        [idx] => ChangeIndex::Found(*idx),
+        _ => ChangeIndex::Inconclusive,
     }
+}
 
-    // Check for address reuse
-    let input_scripts: HashSet<_> = prev_outs
+/// Unnecessary input heuristic: if the payment could have been funded without the smallest input,
+/// a wallet would not have selected it, so that output is probably not the payment.
+/// Returns the indices of outputs that are probably change.
+pub(crate) fn unnecessary_input_change_candidates(
+    tx: &Transaction,
+    prev_outs: &[TxOutWithOutpoint],
+) -> Vec<usize> {
+    let Some(fee) = get_fee(tx, prev_outs) else {
+        return vec![];
+    };
+    let Some(smallest_input) = prev_outs.iter().map(|prev_out| prev_out.txout.value).min() else {
+        return vec![];
+    };
+    if prev_outs.len() < 2 {
+        return vec![];
+    }
+    let input_sum = prev_outs
         .iter()
-        .map(|txout| txout.txout.script_pubkey.clone())
-        .collect();
+        .map(|prev_out| prev_out.txout.value)
+        .sum::<Amount>();
 
-    let shared_scripts: Vec<_> = tx
-        .output
+    tx.output
         .iter()
-        .map(|txout| txout.script_pubkey.clone())
-        .filter(|script| input_scripts.contains(script))
-        .collect();
+        .enumerate()
+        .filter(|(_, txout)| input_sum - smallest_input >= txout.value + fee)
+        .map(|(i, _)| i)
+        .collect()
+}
 
-    if shared_scripts.len() == 1 {
-        if let Some(idx) = tx
-            .output
-            .iter()
-            .position(|txout| txout.script_pubkey == shared_scripts[0])
-        {
-            return ChangeIndex::Found(idx);
-        }
-    }
+/// Unnecessary input heuristic as a change index
+pub(crate) fn unnecessary_input_change_index(
+    tx: &Transaction,
+    prev_outs: &[TxOutWithOutpoint],
+) -> ChangeIndex {
+    change_index_from_candidates(tx, &unnecessary_input_change_candidates(tx, prev_outs))
+}
+
+/// Optimal change heuristic: a wallet would not select an input larger than the change it gets back,
+/// so an output smaller than every input is probably change.
+/// Returns the indices of outputs that are probably change.
+pub(crate) fn optimal_change_candidates(
+    tx: &Transaction,
+    prev_outs: &[TxOutWithOutpoint],
+) -> Vec<usize> {
+    let Some(smallest_input) = prev_outs.iter().map(|prev_out| prev_out.txout.value).min() else {
+        return vec![];
+    };
 
-    // Check for non-round amounts
-    let possible_indices: Vec<_> = tx
-        .output
+    tx.output
         .iter()
         .enumerate()
-        .filter(|(_, txout)| txout.value.to_sat() % 100 != 0)
+        .filter(|(_, txout)| txout.value < smallest_input)
         .map(|(i, _)| i)
-        .collect();
-
-    if possible_indices.len() == 1 {
-        return ChangeIndex::Found(possible_indices[0]);
-    }
+        .collect()
+}
 
-    ChangeIndex::Inconclusive
+/// Optimal change heuristic as a change index
+pub(crate) fn optimal_change_index(
+    tx: &Transaction,
+    prev_outs: &[TxOutWithOutpoint],
+) -> ChangeIndex {
+    change_index_from_candidates(tx, &optimal_change_candidates(tx, prev_outs))
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -225,3 +242,87 @@ pub(crate) fn get_output_types(tx: &Transaction) -> Vec<OutputType> {
     }
     output_types
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{payment, ScriptType};
+    use proptest::{collection::vec, prelude::*};
+
+    #[test]
+    fn test_unnecessary_input() {
+        // Paying 8_000 only needs the 20_000 input, so it is not the payment
+        let (tx, prev_outs) = payment(
+            &[10_000, 20_000],
+            &[
+                (8_000, ScriptType::P2shP2wpkh),
+                (21_000, ScriptType::P2shP2wpkh),
+            ],
+        )
+        .build_with_prevouts();
+        assert_eq!(
+            unnecessary_input_change_candidates(&tx, &prev_outs),
+            vec![0]
+        );
+        assert_eq!(
+            unnecessary_input_change_index(&tx, &prev_outs),
+            ChangeIndex::Found(0)
+        );
+    }
+
+    #[test]
+    fn test_unnecessary_input_single_input() {
+        let (tx, prev_outs) = payment(
+            &[20_000],
+            &[
+                (12_000, ScriptType::P2shP2wpkh),
+                (7_000, ScriptType::P2shP2wpkh),
+            ],
+        )
+        .build_with_prevouts();
+        assert_eq!(
+            unnecessary_input_change_index(&tx, &prev_outs),
+            ChangeIndex::Inconclusive
+        );
+    }
+
+    #[test]
+    fn test_optimal_change() {
+        let (tx, prev_outs) = payment(
+            &[10_000, 20_000],
+            &[
+                (25_000, ScriptType::P2shP2wpkh),
+                (4_000, ScriptType::P2shP2wpkh),
+            ],
+        )
+        .build_with_prevouts();
+        assert_eq!(optimal_change_candidates(&tx, &prev_outs), vec![1]);
+        assert_eq!(optimal_change_index(&tx, &prev_outs), ChangeIndex::Found(1));
+
+        let (tx, prev_outs) = payment(
+            &[10_000, 20_000],
+            &[
+                (5_000, ScriptType::P2shP2wpkh),
+                (4_000, ScriptType::P2shP2wpkh),
+            ],
+        )
+        .build_with_prevouts();
+        assert_eq!(
+            optimal_change_index(&tx, &prev_outs),
+            ChangeIndex::Inconclusive
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_single_output_has_no_change(
+            inputs in vec(1_000u64..1_000_000, 1..5),
+            output in 0u64..1_000,
+        ) {
+            let (tx, prev_outs) = payment(&inputs, &[(output, ScriptType::P2shP2wpkh)]).build_with_prevouts();
+            prop_assert_eq!(get_change_index(&tx, &prev_outs), ChangeIndex::NoChange);
+            prop_assert_eq!(unnecessary_input_change_index(&tx, &prev_outs), ChangeIndex::NoChange);
+            prop_assert_eq!(optimal_change_index(&tx, &prev_outs), ChangeIndex::NoChange);
+        }
+    }
+}
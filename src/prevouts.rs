@@ -0,0 +1,399 @@
+//! Looking up the outputs spent by transaction inputs.
+//!
+//! [`PrevoutLookup`] resolves outpoints from any source: previous transactions, a node or an index.
+//! [`PrevoutIndex`] is a lookup that is kept up to date while scanning blocks in height order,
+//! see [`crate::blocks::scan_blocks`]. With the `redb` feature, `PrevoutStore` keeps the index
+//! on disk so scans can be resumed.
+
+use std::collections::HashMap;
+
+use bitcoin::{Block, BlockHash, OutPoint, Transaction, TxOut};
+
+use crate::util::TxOutWithOutpoint;
+
+/// An output spent by a transaction input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prevout {
+    pub txout: TxOut,
+    /// Height of the block confirming the output, None if unconfirmed or unknown
+    pub height: Option<u32>,
+}
+
+/// A source of the outputs spent by transaction inputs
+pub trait PrevoutLookup {
+    /// Returns None if the output is unknown to this source
+    fn prevout(&self, outpoint: &OutPoint) -> Result<Option<Prevout>, String>;
+
+    /// Looks up several outputs at once, in order.
+    /// Remote sources override this to batch their requests.
+    fn prevouts(&self, outpoints: &[OutPoint]) -> Result<Vec<Option<Prevout>>, String> {
+        outpoints
+            .iter()
+            .map(|outpoint| self.prevout(outpoint))
+            .collect()
+    }
+}
+
+/// A prevout lookup built by connecting blocks in height order
+pub trait PrevoutIndex: PrevoutLookup {
+    /// Height and hash of the last connected block, None if no block was connected yet
+    fn tip(&self) -> Result<Option<(u32, BlockHash)>, String>;
+
+    /// Adds the outputs created by the block and removes the outputs it spends
+    fn connect_block(&mut self, block: &Block, height: u32) -> Result<(), String>;
+}
+
+/// Looks up the output spent by each input in the previous transactions. Heights are unknown.
+impl PrevoutLookup for [Transaction] {
+    fn prevout(&self, outpoint: &OutPoint) -> Result<Option<Prevout>, String> {
+        Ok(self
+            .iter()
+            .find(|prev_tx| prev_tx.compute_txid() == outpoint.txid)
+            .and_then(|prev_tx| prev_tx.output.get(outpoint.vout as usize))
+            .map(|txout| Prevout {
+                txout: txout.clone(),
+                height: None,
+            }))
+    }
+}
+
+impl PrevoutLookup for HashMap<OutPoint, Prevout> {
+    fn prevout(&self, outpoint: &OutPoint) -> Result<Option<Prevout>, String> {
+        Ok(self.get(outpoint).cloned())
+    }
+}
+
+/// An in-memory index, which has to be rebuilt from the genesis block on every scan
+impl PrevoutIndex for HashMap<OutPoint, Prevout> {
+    fn tip(&self) -> Result<Option<(u32, BlockHash)>, String> {
+        Ok(None)
+    }
+
+    fn connect_block(&mut self, block: &Block, height: u32) -> Result<(), String> {
+        for (outpoint, prevout) in block_changes(block, height) {
+            match prevout {
+                Some(prevout) => self.insert(outpoint, prevout),
+                None => self.remove(&outpoint),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// The outputs a block creates and spends, in the order they have to be applied.
+/// Unspendable OP_RETURN outputs are never added.
+fn block_changes(block: &Block, height: u32) -> Vec<(OutPoint, Option<Prevout>)> {
+    let mut changes = Vec::new();
+    for tx in block.txdata.iter() {
+        if !tx.is_coinbase() {
+            changes.extend(tx.input.iter().map(|txin| (txin.previous_output, None)));
+        }
+        let txid = tx.compute_txid();
+        for (vout, txout) in tx.output.iter().enumerate() {
+            if !txout.script_pubkey.is_op_return() {
+                let prevout = Prevout {
+                    txout: txout.clone(),
+                    height: Some(height),
+                };
+                changes.push((OutPoint::new(txid, vout as u32), Some(prevout)));
+            }
+        }
+    }
+    changes
+}
+
+/// Outputs created by the transactions of a block, on top of another lookup
+pub(crate) struct Overlay<'a, L: ?Sized> {
+    pub(crate) lookup: &'a L,
+    pub(crate) created: HashMap<OutPoint, Prevout>,
+}
+
+impl<'a, L: PrevoutLookup + ?Sized> Overlay<'a, L> {
+    pub(crate) fn new(lookup: &'a L) -> Self {
+        Self {
+            lookup,
+            created: HashMap::new(),
+        }
+    }
+
+    /// Adds the outputs of the transaction, confirmed at `height` if known
+    pub(crate) fn add_outputs(&mut self, tx: &Transaction, height: Option<u32>) {
+        let txid = tx.compute_txid();
+        for (vout, txout) in tx.output.iter().enumerate() {
+            let prevout = Prevout {
+                txout: txout.clone(),
+                height,
+            };
+            self.created
+                .insert(OutPoint::new(txid, vout as u32), prevout);
+        }
+    }
+}
+
+impl<L: PrevoutLookup + ?Sized> PrevoutLookup for Overlay<'_, L> {
+    fn prevout(&self, outpoint: &OutPoint) -> Result<Option<Prevout>, String> {
+        match self.created.get(outpoint) {
+            Some(prevout) => Ok(Some(prevout.clone())),
+            None => self.lookup.prevout(outpoint),
+        }
+    }
+
+    fn prevouts(&self, outpoints: &[OutPoint]) -> Result<Vec<Option<Prevout>>, String> {
+        let missing = outpoints
+            .iter()
+            .filter(|outpoint| !self.created.contains_key(outpoint))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut found = self.lookup.prevouts(&missing)?.into_iter();
+        Ok(outpoints
+            .iter()
+            .map(|outpoint| match self.created.get(outpoint) {
+                Some(prevout) => Some(prevout.clone()),
+                None => found.next().flatten(),
+            })
+            .collect())
+    }
+}
+
+/// Looks up the output spent by each input, None if any of them is unknown
+pub(crate) fn lookup_prevouts<L: PrevoutLookup + ?Sized>(
+    tx: &Transaction,
+    lookup: &L,
+) -> Result<Option<Vec<TxOutWithOutpoint>>, String> {
+    let outpoints = tx
+        .input
+        .iter()
+        .map(|txin| txin.previous_output)
+        .collect::<Vec<_>>();
+    let prevouts = lookup.prevouts(&outpoints)?;
+    Ok(outpoints
+        .into_iter()
+        .zip(prevouts)
+        .map(|(outpoint, prevout)| {
+            prevout.map(|prevout| TxOutWithOutpoint {
+                txout: prevout.txout,
+                outpoint,
+                height: prevout.height,
+            })
+        })
+        .collect())
+}
+
+#[cfg(feature = "redb")]
+pub use store::PrevoutStore;
+
+#[cfg(feature = "redb")]
+mod store {
+    use std::path::Path;
+
+    use bitcoin::{
+        consensus::{deserialize, serialize},
+        Block, BlockHash, OutPoint,
+    };
+    use redb::{Database, TableDefinition};
+
+    use super::{block_changes, Prevout, PrevoutIndex, PrevoutLookup};
+
+    /// Serialized outpoint to confirmation height followed by the serialized output
+    const PREVOUTS: TableDefinition<&[u8], &[u8]> = TableDefinition::new("prevouts");
+    /// Height of the last connected block followed by its serialized hash
+    const META: TableDefinition<&str, &[u8]> = TableDefinition::new("meta");
+    const TIP_KEY: &str = "tip";
+
+    /// An on-disk index of unspent outputs
+    pub struct PrevoutStore {
+        db: Database,
+    }
+
+    impl PrevoutStore {
+        /// Opens the store at `path`, creating it if it does not exist
+        pub fn open(path: &Path) -> Result<Self, String> {
+            let db = Database::create(path).map_err(|e| e.to_string())?;
+            // Create the tables so reads never find them missing
+            let txn = db.begin_write().map_err(|e| e.to_string())?;
+            txn.open_table(PREVOUTS).map_err(|e| e.to_string())?;
+            txn.open_table(META).map_err(|e| e.to_string())?;
+            txn.commit().map_err(|e| e.to_string())?;
+            Ok(Self { db })
+        }
+    }
+
+    impl PrevoutLookup for PrevoutStore {
+        fn prevout(&self, outpoint: &OutPoint) -> Result<Option<Prevout>, String> {
+            let txn = self.db.begin_read().map_err(|e| e.to_string())?;
+            let table = txn.open_table(PREVOUTS).map_err(|e| e.to_string())?;
+            let Some(value) = table
+                .get(serialize(outpoint).as_slice())
+                .map_err(|e| e.to_string())?
+            else {
+                return Ok(None);
+            };
+            let value = value.value();
+            if value.len() < 4 {
+                return Err(format!("corrupt prevout {}", outpoint));
+            }
+            let (height, txout) = value.split_at(4);
+            Ok(Some(Prevout {
+                txout: deserialize(txout).map_err(|e| e.to_string())?,
+                height: Some(u32::from_le_bytes(height.try_into().expect("4 bytes"))),
+            }))
+        }
+    }
+
+    impl PrevoutIndex for PrevoutStore {
+        fn tip(&self) -> Result<Option<(u32, BlockHash)>, String> {
+            let txn = self.db.begin_read().map_err(|e| e.to_string())?;
+            let table = txn.open_table(META).map_err(|e| e.to_string())?;
+            let Some(value) = table.get(TIP_KEY).map_err(|e| e.to_string())? else {
+                return Ok(None);
+            };
+            let value = value.value();
+            if value.len() < 4 {
+                return Err("corrupt tip".to_string());
+            }
+            let (height, hash) = value.split_at(4);
+            Ok(Some((
+                u32::from_le_bytes(height.try_into().expect("4 bytes")),
+                deserialize(hash).map_err(|e| e.to_string())?,
+            )))
+        }
+
+        /// Connects the block atomically, so an interrupted scan resumes at a block boundary
+        fn connect_block(&mut self, block: &Block, height: u32) -> Result<(), String> {
+            let txn = self.db.begin_write().map_err(|e| e.to_string())?;
+            {
+                let mut prevouts = txn.open_table(PREVOUTS).map_err(|e| e.to_string())?;
+                for (outpoint, prevout) in block_changes(block, height) {
+                    let key = serialize(&outpoint);
+                    match prevout {
+                        Some(prevout) => {
+                            let mut value = height.to_le_bytes().to_vec();
+                            value.extend(serialize(&prevout.txout));
+                            prevouts
+                                .insert(key.as_slice(), value.as_slice())
+                                .map_err(|e| e.to_string())?;
+                        }
+                        None => {
+                            prevouts.remove(key.as_slice()).map_err(|e| e.to_string())?;
+                        }
+                    }
+                }
+                let mut tip = height.to_le_bytes().to_vec();
+                tip.extend(serialize(&block.block_hash()));
+                let mut meta = txn.open_table(META).map_err(|e| e.to_string())?;
+                meta.insert(TIP_KEY, tip.as_slice())
+                    .map_err(|e| e.to_string())?;
+            }
+            txn.commit().map_err(|e| e.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{ScriptType, TxBuilder},
+        util::get_prevouts,
+    };
+    use bitcoin::{
+        absolute::LockTime, block::Header, block::Version as BlockVersion, hashes::Hash,
+        transaction::Version, Amount, BlockHash, CompactTarget, ScriptBuf, Sequence, TxIn,
+        TxMerkleNode, Witness,
+    };
+
+    fn coinbase(height: u32) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(height.to_le_bytes().to_vec()),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(50_0000_0000),
+                script_pubkey: ScriptBuf::new_op_return([]),
+            }],
+        }
+    }
+
+    fn block(height: u32, mut txdata: Vec<Transaction>) -> Block {
+        txdata.insert(0, coinbase(height));
+        Block {
+            header: Header {
+                version: BlockVersion::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        }
+    }
+
+    /// Connects a block funding a transaction, then a block with the transaction
+    fn check_index(index: &mut impl PrevoutIndex) {
+        let (tx, prev_txs) = TxBuilder::new(0)
+            .input(Amount::from_sat(100_000), ScriptType::P2wpkh)
+            .output(Amount::from_sat(99_000), ScriptType::P2tr)
+            .build();
+        let spent = tx.input[0].previous_output;
+        assert!(lookup_prevouts(&tx, index).unwrap().is_none());
+
+        index.connect_block(&block(7, prev_txs), 7).unwrap();
+        let prevouts = lookup_prevouts(&tx, index).unwrap().unwrap();
+        assert_eq!(prevouts[0].txout.value, Amount::from_sat(100_000));
+        assert_eq!(prevouts[0].height, Some(7));
+        // OP_RETURN outputs are never spendable
+        let coinbase = OutPoint::new(coinbase(7).compute_txid(), 0);
+        assert_eq!(index.prevout(&coinbase).unwrap(), None);
+
+        index.connect_block(&block(8, vec![tx.clone()]), 8).unwrap();
+        assert_eq!(index.prevout(&spent).unwrap(), None);
+        let created = index
+            .prevout(&OutPoint::new(tx.compute_txid(), 0))
+            .unwrap()
+            .unwrap();
+        assert_eq!(created.height, Some(8));
+    }
+
+    #[test]
+    fn test_prev_txs_lookup() {
+        let (tx, prev_txs) = TxBuilder::new(1)
+            .input(Amount::from_sat(1_000), ScriptType::P2pkh)
+            .build();
+        let prevouts = lookup_prevouts(&tx, prev_txs.as_slice()).unwrap().unwrap();
+        assert_eq!(prevouts, get_prevouts(&tx, &prev_txs).unwrap());
+        assert!(lookup_prevouts(&tx, &prev_txs[1..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_memory_index() {
+        let mut index = HashMap::new();
+        check_index(&mut index);
+        // The in-memory index does not remember its tip
+        assert_eq!(index.tip().unwrap(), None);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn test_prevout_store() {
+        let path =
+            std::env::temp_dir().join(format!("wallet-prevouts-{}.redb", std::process::id()));
+        {
+            let mut store = PrevoutStore::open(&path).unwrap();
+            assert_eq!(store.tip().unwrap(), None);
+            check_index(&mut store);
+        }
+        // Reopening resumes after the last connected block
+        let store = PrevoutStore::open(&path).unwrap();
+        assert_eq!(
+            store.tip().unwrap(),
+            Some((8, block(8, vec![]).block_hash()))
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! Reading the previous outputs and signatures of a PSBT, so it can be analysed before it is
+//! fully signed. See [`crate::heuristics::Heuristics::from_psbt`].
+
+use bitcoin::{Psbt, PublicKey, ScriptBuf, Transaction, TxIn};
+
+use crate::util::{extract_signatures, TxOutWithOutpoint};
+
+/// The unsigned transaction, with the scripts of finalized inputs filled in
+pub(crate) fn psbt_transaction(psbt: &Psbt) -> Transaction {
+    let mut tx = psbt.unsigned_tx.clone();
+    for (txin, input) in tx.input.iter_mut().zip(psbt.inputs.iter()) {
+        if let Some(script_sig) = &input.final_script_sig {
+            txin.script_sig = script_sig.clone();
+        }
+        if let Some(witness) = &input.final_script_witness {
+            txin.witness = witness.clone();
+        }
+    }
+    tx
+}
+
+/// Whether every input has its final scripts, so the size of the transaction is known
+pub(crate) fn psbt_finalized(psbt: &Psbt) -> bool {
+    psbt.inputs
+        .iter()
+        .all(|input| input.final_script_sig.is_some() || input.final_script_witness.is_some())
+}
+
+/// The output spent by each input, from its `witness_utxo` or else its `non_witness_utxo`
+pub(crate) fn psbt_prevouts(psbt: &Psbt) -> Result<Vec<TxOutWithOutpoint>, String> {
+    psbt.unsigned_tx
+        .input
+        .iter()
+        .zip(psbt.inputs.iter())
+        .enumerate()
+        .map(|(i, (txin, input))| {
+            let outpoint = txin.previous_output;
+            let txout = match (&input.witness_utxo, &input.non_witness_utxo) {
+                (Some(txout), _) => txout.clone(),
+                (None, Some(prev_tx)) => {
+                    if prev_tx.compute_txid() != outpoint.txid {
+                        return Err(format!(
+                            "input {}: non_witness_utxo does not match the outpoint",
+                            i
+                        ));
+                    }
+                    prev_tx
+                        .output
+                        .get(outpoint.vout as usize)
+                        .cloned()
+                        .ok_or_else(|| {
+                            format!(
+                                "input {}: non_witness_utxo has no output {}",
+                                i, outpoint.vout
+                            )
+                        })?
+                }
+                (None, None) => return Err(format!("input {} has no previous output", i)),
+            };
+            Ok(TxOutWithOutpoint {
+                txout,
+                outpoint,
+                height: None,
+            })
+        })
+        .collect()
+}
+
+/// Whether an input is to be signed with an uncompressed pubkey, known from its partial
+/// signatures, or from a derived pubkey whose uncompressed form the P2PKH output pays to.
+/// The unsigned transaction has no scriptSigs revealing the pubkeys yet.
+pub(crate) fn psbt_uncompressed_pubkeys(psbt: &Psbt, prev_outs: &[TxOutWithOutpoint]) -> bool {
+    psbt.inputs
+        .iter()
+        .zip(prev_outs.iter())
+        .any(|(input, prev_out)| {
+            input.partial_sigs.keys().any(|pubkey| !pubkey.compressed)
+                || input.bip32_derivation.keys().any(|pubkey| {
+                    let pubkey = PublicKey::new_uncompressed(*pubkey);
+                    prev_out.txout.script_pubkey == ScriptBuf::new_p2pkh(&pubkey.pubkey_hash())
+                })
+        })
+}
+
+/// The serialized signatures of every input, None if an input has no signature yet.
+/// Signatures are taken from the final scripts of finalized inputs, and from the partial
+/// signatures of the others.
+pub(crate) fn psbt_signatures(psbt: &Psbt) -> Option<Vec<Vec<u8>>> {
+    let mut signatures = Vec::new();
+    for input in psbt.inputs.iter() {
+        let input_signatures =
+            if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+                extract_signatures(&TxIn {
+                    script_sig: input.final_script_sig.clone().unwrap_or_default(),
+                    witness: input.final_script_witness.clone().unwrap_or_default(),
+                    ..Default::default()
+                })
+            } else {
+                input
+                    .partial_sigs
+                    .values()
+                    .map(|sig| sig.to_vec())
+                    .chain(input.tap_key_sig.iter().map(|sig| sig.to_vec()))
+                    .chain(input.tap_script_sigs.values().map(|sig| sig.to_vec()))
+                    .collect()
+            };
+        if input_signatures.is_empty() {
+            return None;
+        }
+        signatures.extend(input_signatures);
+    }
+    Some(signatures)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        change::ChangeHints,
+        heuristics::Heuristics,
+        test_utils::{ScriptType, TxBuilder},
+        util::get_prevouts,
+    };
+    use bitcoin::{
+        ecdsa, script::Instruction, taproot, Amount, Psbt, PublicKey, ScriptBuf, Transaction, TxIn,
+        Witness,
+    };
+
+    fn spend() -> (Transaction, Vec<Transaction>, Psbt) {
+        let (signed, prev_txs) = TxBuilder::new(0)
+            .input(Amount::from_sat(100_000), ScriptType::P2wpkh)
+            .input(Amount::from_sat(50_000), ScriptType::P2pkh)
+            .input(Amount::from_sat(20_000), ScriptType::P2tr)
+            .output(Amount::from_sat(120_000), ScriptType::P2wpkh)
+            .change(Amount::from_sat(48_000), ScriptType::P2wpkh)
+            .build();
+        let unsigned = Transaction {
+            input: signed
+                .input
+                .iter()
+                .map(|txin| TxIn {
+                    script_sig: ScriptBuf::new(),
+                    witness: Witness::new(),
+                    ..txin.clone()
+                })
+                .collect(),
+            ..signed.clone()
+        };
+
+        let mut psbt = Psbt::from_unsigned_tx(unsigned).unwrap();
+        for (input, txin) in psbt.inputs.iter_mut().zip(signed.input.iter()) {
+            let prev_tx = prev_txs
+                .iter()
+                .find(|prev_tx| prev_tx.compute_txid() == txin.previous_output.txid)
+                .unwrap();
+            if txin.witness.is_empty() {
+                input.non_witness_utxo = Some(prev_tx.clone());
+            } else {
+                input.witness_utxo =
+                    Some(prev_tx.output[txin.previous_output.vout as usize].clone());
+            }
+        }
+        (signed, prev_txs, psbt)
+    }
+
+    /// Adds the signatures of the signed input to the PSBT input as partial signatures
+    fn add_partial_signatures(psbt: &mut Psbt, index: usize, signed: &TxIn) {
+        let input = &mut psbt.inputs[index];
+        let pushes = signed
+            .script_sig
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(bytes)) => Some(bytes.as_bytes().to_vec()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let items = if pushes.is_empty() {
+            signed.witness.to_vec()
+        } else {
+            pushes
+        };
+        match items.as_slice() {
+            [sig, pubkey] => {
+                input.partial_sigs.insert(
+                    PublicKey::from_slice(pubkey).unwrap(),
+                    ecdsa::Signature::from_slice(sig).unwrap(),
+                );
+            }
+            [sig] => input.tap_key_sig = Some(taproot::Signature::from_slice(sig).unwrap()),
+            _ => panic!("unexpected spend"),
+        }
+    }
+
+    #[test]
+    fn test_psbt_heuristics() {
+        let (signed, prev_txs, mut psbt) = spend();
+        let expected = Heuristics::from_prevouts(
+            &signed,
+            &get_prevouts(&signed, &prev_txs).unwrap(),
+            &ChangeHints::default(),
+        );
+
+        let unsigned = Heuristics::from_psbt(&psbt).unwrap();
+        assert_eq!(unsigned.low_r_grinding, None);
+        assert_eq!(unsigned.sighash_types, None);
+        assert_eq!(unsigned.round_feerate_estimators, None);
+        assert_eq!(unsigned.input_types, expected.input_types);
+        assert_eq!(unsigned.output_types, expected.output_types);
+        assert_eq!(unsigned.change_index, expected.change_index);
+        assert!(unsigned.fingerprint().canonical.ends_with("|lowr:?|sh:?"));
+
+        // Signature heuristics stay unknown until every input is signed
+        add_partial_signatures(&mut psbt, 0, &signed.input[0]);
+        add_partial_signatures(&mut psbt, 1, &signed.input[1]);
+        let partially_signed = Heuristics::from_psbt(&psbt).unwrap();
+        assert_eq!(partially_signed.low_r_grinding, None);
+        add_partial_signatures(&mut psbt, 2, &signed.input[2]);
+        let signed_psbt = Heuristics::from_psbt(&psbt).unwrap();
+        assert_eq!(signed_psbt.low_r_grinding, expected.low_r_grinding);
+        assert_eq!(signed_psbt.sighash_types, expected.sighash_types);
+        // Partial signatures do not tell the size of the final scripts
+        assert_eq!(signed_psbt.round_feerate_estimators, None);
+
+        for (input, txin) in psbt.inputs.iter_mut().zip(signed.input.iter()) {
+            input.partial_sigs.clear();
+            input.tap_key_sig = None;
+            if txin.witness.is_empty() {
+                input.final_script_sig = Some(txin.script_sig.clone());
+            } else {
+                input.final_script_witness = Some(txin.witness.clone());
+            }
+        }
+        let finalized = Heuristics::from_psbt(&psbt).unwrap();
+        assert_eq!(finalized.fingerprint(), expected.fingerprint());
+        assert!(expected.round_feerate_estimators.is_some());
+        assert_eq!(
+            finalized.round_feerate_estimators,
+            expected.round_feerate_estimators
+        );
+    }
+
+    #[test]
+    fn test_psbt_missing_prevouts() {
+        let (signed, _, mut psbt) = spend();
+        psbt.inputs[1].non_witness_utxo = Some(signed);
+        assert_eq!(
+            Heuristics::from_psbt(&psbt).unwrap_err(),
+            "input 1: non_witness_utxo does not match the outpoint"
+        );
+
+        psbt.inputs[0].witness_utxo = None;
+        assert_eq!(
+            Heuristics::from_psbt(&psbt).unwrap_err(),
+            "input 0 has no previous output"
+        );
+    }
+}
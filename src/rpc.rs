@@ -0,0 +1,503 @@
+//! Looking up prevouts from a Bitcoin Core node over JSON-RPC.
+//!
+//! Unspent outputs, including those of mempool transactions, are found with `gettxout`.
+//! Spent outputs are found with `getrawtransaction`, which requires the node to run with
+//! `-txindex`. Lookups are sent as batch requests, and outputs confirmed on the node are cached.
+
+use std::{collections::HashMap, fs, path::Path, sync::Mutex, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bitcoin::{
+    consensus::encode::deserialize_hex, Amount, OutPoint, ScriptBuf, Transaction, TxOut,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::prevouts::{Prevout, PrevoutLookup};
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Error code for transactions unknown to the node
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    id: usize,
+    result: Value,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+/// The result of a call, or the error the node answered it with
+type Outcome = Result<Value, RpcError>;
+
+#[derive(Debug, Deserialize)]
+struct TxOutResult {
+    confirmations: u32,
+    /// In BTC
+    value: f64,
+    #[serde(rename = "scriptPubKey")]
+    script_pubkey: ScriptPubKey,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScriptPubKey {
+    hex: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawTransaction {
+    hex: String,
+    /// Omitted for mempool transactions
+    #[serde(default)]
+    confirmations: u32,
+}
+
+/// Height of the block confirming an output, given the block count the confirmations are
+/// relative to. None if unconfirmed, or if the block count is unknown or inconsistent with them.
+fn confirmation_height(block_count: Option<u32>, confirmations: u32) -> Option<u32> {
+    block_count?.checked_sub(confirmations.checked_sub(1)?)
+}
+
+/// A prevout lookup backed by a Bitcoin Core node
+pub struct RpcClient {
+    url: String,
+    agent: ureq::Agent,
+    authorization: String,
+    /// Outputs confirmed on the node, which do not change
+    confirmed: Mutex<HashMap<OutPoint, Prevout>>,
+}
+
+impl RpcClient {
+    /// `url` is the RPC endpoint, e.g. `http://127.0.0.1:8332`
+    pub fn new(url: &str, user: &str, password: &str) -> Self {
+        let credentials = STANDARD.encode(format!("{}:{}", user, password));
+        Self {
+            url: url.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            authorization: format!("Basic {}", credentials),
+            confirmed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Authenticates with the `.cookie` file the node writes to its data directory
+    pub fn with_cookie_file(url: &str, path: &Path) -> Result<Self, String> {
+        let cookie = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let (user, password) = cookie
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("{}: invalid cookie file", path.display()))?;
+        Ok(Self::new(url, user, password))
+    }
+
+    /// Sends the calls as a single batch request, returning the outcome of each call in order
+    fn batch(&self, calls: &[(&str, Value)]) -> Result<Vec<Outcome>, String> {
+        let request = calls
+            .iter()
+            .enumerate()
+            .map(|(id, (method, params))| {
+                json!({ "jsonrpc": "1.0", "id": id, "method": method, "params": params })
+            })
+            .collect::<Vec<_>>();
+        let response = match self
+            .agent
+            .post(&self.url)
+            .set("Authorization", &self.authorization)
+            .set("Content-Type", "application/json")
+            .send_string(&Value::Array(request).to_string())
+        {
+            Ok(response) => response,
+            Err(ureq::Error::Status(code, _)) => {
+                return Err(format!("{}: HTTP status {}", self.url, code))
+            }
+            Err(e) => return Err(format!("{}: {}", self.url, e)),
+        };
+        let body = response
+            .into_string()
+            .map_err(|e| format!("{}: {}", self.url, e))?;
+        let responses: Vec<RpcResponse> =
+            serde_json::from_str(&body).map_err(|e| format!("{}: {}", self.url, e))?;
+
+        // Responses to a batch may come in any order
+        let mut outcomes = calls.iter().map(|_| None).collect::<Vec<_>>();
+        for response in responses {
+            let outcome = match response.error {
+                Some(error) => Err(error),
+                None => Ok(response.result),
+            };
+            if let Some(slot) = outcomes.get_mut(response.id) {
+                *slot = Some(outcome);
+            }
+        }
+        outcomes
+            .into_iter()
+            .enumerate()
+            .map(|(id, outcome)| outcome.ok_or_else(|| format!("no response to {}", calls[id].0)))
+            .collect()
+    }
+
+    /// Sends the calls between two `getblockcount` calls, returning the results and the block
+    /// count, which is None if a block was connected or disconnected while the calls ran
+    fn batch_with_block_count(
+        &self,
+        calls: impl IntoIterator<Item = (&'static str, Value)>,
+    ) -> Result<(Option<u32>, Vec<Outcome>), String> {
+        let getblockcount = ("getblockcount", json!([]));
+        let calls = [getblockcount.clone()]
+            .into_iter()
+            .chain(calls)
+            .chain([getblockcount])
+            .collect::<Vec<_>>();
+        let mut outcomes = self.batch(&calls)?;
+        let block_count = |outcome: Outcome| {
+            let block_count = outcome.map_err(|e| format!("getblockcount: {}", e.message))?;
+            serde_json::from_value::<u32>(block_count).map_err(|e| format!("getblockcount: {}", e))
+        };
+        // Calls of a batch run in order, so the results are relative to the same tip
+        // only if the block count did not change
+        let before = block_count(outcomes.remove(0))?;
+        let after = block_count(outcomes.pop().expect("Sent getblockcount last"))?;
+        Ok(((before == after).then_some(before), outcomes))
+    }
+
+    /// Looks up outputs that are not spent yet
+    fn unspent(&self, outpoints: &[OutPoint]) -> Result<HashMap<OutPoint, Prevout>, String> {
+        let (block_count, outcomes) =
+            self.batch_with_block_count(outpoints.iter().map(|outpoint| {
+                (
+                    "gettxout",
+                    json!([outpoint.txid.to_string(), outpoint.vout, true]),
+                )
+            }))?;
+
+        let mut found = HashMap::new();
+        for (outpoint, outcome) in outpoints.iter().zip(outcomes) {
+            let result = outcome.map_err(|e| format!("gettxout {}: {}", outpoint, e.message))?;
+            if result.is_null() {
+                continue;
+            }
+            let result: TxOutResult = serde_json::from_value(result)
+                .map_err(|e| format!("gettxout {}: {}", outpoint, e))?;
+            let txout = TxOut {
+                value: Amount::from_btc(result.value)
+                    .map_err(|e| format!("gettxout {}: {}", outpoint, e))?,
+                script_pubkey: ScriptBuf::from_hex(&result.script_pubkey.hex)
+                    .map_err(|e| format!("gettxout {}: {}", outpoint, e))?,
+            };
+            let height = confirmation_height(block_count, result.confirmations);
+            found.insert(*outpoint, Prevout { txout, height });
+        }
+        Ok(found)
+    }
+
+    /// Looks up outputs, spent or not, from the transactions creating them
+    fn created(&self, outpoints: &[OutPoint]) -> Result<HashMap<OutPoint, Prevout>, String> {
+        let mut txids = outpoints
+            .iter()
+            .map(|outpoint| outpoint.txid)
+            .collect::<Vec<_>>();
+        txids.sort();
+        txids.dedup();
+        let (block_count, outcomes) = self.batch_with_block_count(
+            txids
+                .iter()
+                .map(|txid| ("getrawtransaction", json!([txid.to_string(), true]))),
+        )?;
+
+        let mut found = HashMap::new();
+        for (txid, outcome) in txids.iter().zip(outcomes) {
+            let result = match outcome {
+                Ok(result) => result,
+                Err(e) if e.code == RPC_INVALID_ADDRESS_OR_KEY => continue,
+                Err(e) => return Err(format!("getrawtransaction {}: {}", txid, e.message)),
+            };
+            let raw: RawTransaction = serde_json::from_value(result)
+                .map_err(|e| format!("getrawtransaction {}: {}", txid, e))?;
+            let tx: Transaction = deserialize_hex(&raw.hex)
+                .map_err(|e| format!("getrawtransaction {}: {}", txid, e))?;
+            let height = confirmation_height(block_count, raw.confirmations);
+            for outpoint in outpoints.iter().filter(|outpoint| outpoint.txid == *txid) {
+                if let Some(txout) = tx.output.get(outpoint.vout as usize) {
+                    let prevout = Prevout {
+                        txout: txout.clone(),
+                        height,
+                    };
+                    found.insert(*outpoint, prevout);
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+impl PrevoutLookup for RpcClient {
+    fn prevout(&self, outpoint: &OutPoint) -> Result<Option<Prevout>, String> {
+        Ok(self.prevouts(&[*outpoint])?.into_iter().next().flatten())
+    }
+
+    /// Takes at most two requests: one for unspent outputs, one for spent outputs
+    fn prevouts(&self, outpoints: &[OutPoint]) -> Result<Vec<Option<Prevout>>, String> {
+        let mut found = {
+            let confirmed = self.confirmed.lock().unwrap();
+            outpoints
+                .iter()
+                .filter_map(|outpoint| Some((*outpoint, confirmed.get(outpoint)?.clone())))
+                .collect::<HashMap<_, _>>()
+        };
+        let mut missing = outpoints
+            .iter()
+            .filter(|outpoint| !found.contains_key(outpoint))
+            .copied()
+            .collect::<Vec<_>>();
+        missing.sort();
+        missing.dedup();
+
+        if !missing.is_empty() {
+            let unspent = self.unspent(&missing)?;
+            let spent = missing
+                .into_iter()
+                .filter(|outpoint| !unspent.contains_key(outpoint))
+                .collect::<Vec<_>>();
+            let fetched = if spent.is_empty() {
+                unspent
+            } else {
+                unspent.into_iter().chain(self.created(&spent)?).collect()
+            };
+
+            let mut confirmed = self.confirmed.lock().unwrap();
+            for (outpoint, prevout) in fetched {
+                if prevout.height.is_some() {
+                    confirmed.insert(outpoint, prevout.clone());
+                }
+                found.insert(outpoint, prevout);
+            }
+        }
+
+        Ok(outpoints
+            .iter()
+            .map(|outpoint| found.get(outpoint).cloned())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        heuristics::Heuristics,
+        test_utils::{ScriptType, TxBuilder},
+    };
+    use bitcoin::{consensus::encode::serialize_hex, hashes::Hash, Txid};
+    use std::{
+        sync::{
+            atomic::{AtomicU32, AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    const BLOCK_COUNT: u32 = 200;
+
+    /// The state of the node: unspent outputs and transactions, with their confirmations
+    struct Node {
+        unspent: HashMap<OutPoint, (TxOut, u32)>,
+        txs: HashMap<Txid, (Transaction, u32)>,
+        /// Whether a block is connected after every `getblockcount`
+        tip_moves: bool,
+        block_count: AtomicU32,
+    }
+
+    impl Node {
+        fn call(&self, method: &str, params: &[Value]) -> Value {
+            let txid = |param: &Value| param.as_str().unwrap().parse::<Txid>().unwrap();
+            match method {
+                "getblockcount" => {
+                    let block_count = self
+                        .block_count
+                        .fetch_add(self.tip_moves as u32, Ordering::SeqCst);
+                    json!({ "result": block_count, "error": null })
+                }
+                "gettxout" => {
+                    let outpoint =
+                        OutPoint::new(txid(&params[0]), params[1].as_u64().unwrap() as u32);
+                    let result = self.unspent.get(&outpoint).map(|(txout, confirmations)| {
+                        json!({
+                            "bestblock": "00".repeat(32),
+                            "confirmations": confirmations,
+                            "value": txout.value.to_btc(),
+                            "scriptPubKey": { "hex": txout.script_pubkey.to_hex_string() },
+                            "coinbase": false,
+                        })
+                    });
+                    json!({ "result": result, "error": null })
+                }
+                "getrawtransaction" => match self.txs.get(&txid(&params[0])) {
+                    Some((tx, confirmations)) => {
+                        let mut result = json!({ "txid": tx.compute_txid().to_string(), "hex": serialize_hex(tx) });
+                        if *confirmations > 0 {
+                            result["confirmations"] = json!(confirmations);
+                        }
+                        json!({ "result": result, "error": null })
+                    }
+                    None => json!({
+                        "result": null,
+                        "error": {
+                            "code": RPC_INVALID_ADDRESS_OR_KEY,
+                            "message": "No such mempool or blockchain transaction",
+                        },
+                    }),
+                },
+                _ => panic!("unexpected method {}", method),
+            }
+        }
+    }
+
+    /// Serves the node until the test exits, counting the requests
+    fn serve(node: Node) -> (String, Arc<AtomicUsize>) {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr());
+        let authorization = format!("Basic {}", STANDARD.encode("user:password"));
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let authorized = request.headers().iter().any(|header| {
+                    header.field.equiv("Authorization") && header.value == authorization
+                });
+                if !authorized {
+                    let response = tiny_http::Response::empty(401);
+                    request.respond(response).unwrap();
+                    continue;
+                }
+
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                let calls: Vec<Value> = serde_json::from_str(&body).unwrap();
+                // Answer in reverse, as batch responses are unordered
+                let responses = calls
+                    .iter()
+                    .rev()
+                    .map(|call| {
+                        let params = call["params"].as_array().unwrap();
+                        let mut response = node.call(call["method"].as_str().unwrap(), params);
+                        response["id"] = call["id"].clone();
+                        response
+                    })
+                    .collect::<Vec<_>>();
+                let response =
+                    tiny_http::Response::from_string(Value::Array(responses).to_string());
+                request.respond(response).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    #[test]
+    fn test_rpc_client() {
+        let (tx, prev_txs) = TxBuilder::new(0)
+            .input(Amount::from_sat(100_000), ScriptType::P2wpkh)
+            .input(Amount::from_sat(50_000), ScriptType::P2tr)
+            .input(Amount::from_sat(20_000), ScriptType::P2pkh)
+            .output(Amount::from_sat(120_000), ScriptType::P2wpkh)
+            .change(Amount::from_sat(49_000), ScriptType::P2wpkh)
+            .build();
+        let outpoints = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        // Unspent and confirmed, spent and confirmed, unspent in the mempool
+        let node = Node {
+            unspent: HashMap::from([
+                (outpoints[0], (prev_txs[0].output[0].clone(), 10)),
+                (outpoints[2], (prev_txs[2].output[0].clone(), 0)),
+            ]),
+            txs: HashMap::from([(prev_txs[1].compute_txid(), (prev_txs[1].clone(), 50))]),
+            tip_moves: false,
+            block_count: AtomicU32::new(BLOCK_COUNT),
+        };
+        let (url, requests) = serve(node);
+        let client = RpcClient::new(&url, "user", "password");
+
+        let heuristics = Heuristics::from_lookup(&tx, &client).unwrap().unwrap();
+        assert_eq!(heuristics.txid, tx.compute_txid());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let prevouts = client.prevouts(&outpoints).unwrap();
+        let heights = prevouts
+            .iter()
+            .map(|prevout| prevout.as_ref().unwrap().height)
+            .collect::<Vec<_>>();
+        assert_eq!(heights, vec![Some(191), Some(151), None]);
+        for (prevout, prev_tx) in prevouts.iter().zip(prev_txs.iter()) {
+            assert_eq!(prevout.as_ref().unwrap().txout, prev_tx.output[0]);
+        }
+        // Only the unconfirmed output is looked up again
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        let unknown = OutPoint::new(Txid::all_zeros(), 0);
+        assert_eq!(client.prevout(&unknown), Ok(None));
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+
+        let unauthorized = RpcClient::new(&url, "user", "wrong");
+        assert!(unauthorized
+            .prevout(&outpoints[0])
+            .unwrap_err()
+            .ends_with("HTTP status 401"));
+
+        let cookie = std::env::temp_dir().join(format!("wallet-rpc-cookie-{}", std::process::id()));
+        fs::write(&cookie, "user:password\n").unwrap();
+        let client = RpcClient::with_cookie_file(&url, &cookie).unwrap();
+        fs::remove_file(&cookie).unwrap();
+        assert!(client.prevout(&outpoints[1]).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_tip_moving_during_lookup() {
+        let (tx, prev_txs) = TxBuilder::new(1)
+            .input(Amount::from_sat(100_000), ScriptType::P2wpkh)
+            .input(Amount::from_sat(50_000), ScriptType::P2wpkh)
+            .output(Amount::from_sat(149_000), ScriptType::P2wpkh)
+            .build();
+        let outpoints = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        let node = Node {
+            unspent: HashMap::from([(outpoints[0], (prev_txs[0].output[0].clone(), 10))]),
+            txs: HashMap::from([(prev_txs[1].compute_txid(), (prev_txs[1].clone(), 50))]),
+            tip_moves: true,
+            block_count: AtomicU32::new(BLOCK_COUNT),
+        };
+        let (url, requests) = serve(node);
+        let client = RpcClient::new(&url, "user", "password");
+
+        // The confirmations may be relative to either tip, so the heights are unknown
+        let prevouts = client.prevouts(&outpoints).unwrap();
+        assert!(prevouts
+            .iter()
+            .all(|prevout| prevout.as_ref().unwrap().height.is_none()));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        // and nothing is cached
+        client.prevouts(&outpoints).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_confirmation_height() {
+        assert_eq!(confirmation_height(Some(200), 1), Some(200));
+        assert_eq!(confirmation_height(Some(200), 10), Some(191));
+        assert_eq!(confirmation_height(Some(200), 0), None);
+        assert_eq!(confirmation_height(None, 10), None);
+        // More confirmations than blocks, e.g. if a reorg happened between the calls
+        assert_eq!(confirmation_height(Some(5), 10), None);
+    }
+}
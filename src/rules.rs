@@ -0,0 +1,450 @@
+//! Learning wallet profiles from labeled transactions.
+//! Instead of hand-maintaining the elimination table in `detect_wallet`, a decision tree is trained
+//! over the traits of labeled transactions and exported as a ruleset, one rule per leaf.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    str::FromStr,
+};
+
+use bitcoin::Transaction;
+
+use crate::{
+    change::ChangeHints, clustering::Traits, heuristics::Heuristics, util::get_prevouts, WalletType,
+};
+
+/// Version of the ruleset text format
+const RULESET_VERSION: u8 = 1;
+
+/// A transaction whose wallet is known, e.g. made by one of our regtest wallets
+#[derive(Debug, Clone)]
+pub struct LabeledTransaction {
+    pub tx: Transaction,
+    /// The transactions whose outputs are spent by `tx`
+    pub prev_txs: Vec<Transaction>,
+    pub wallet: WalletType,
+}
+
+/// Parameters for learning a ruleset
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct TrainingConfig {
+    /// Maximum number of conditions in a rule
+    pub max_depth: u32,
+    /// Nodes with fewer samples than this are not split any further
+    pub min_samples_split: u64,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            min_samples_split: 2,
+        }
+    }
+}
+
+/// A test on the traits of a transaction, e.g. `version=2` or `!sequence~rbf`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct Condition {
+    /// `name=value` for single valued traits, `name~member` for set traits
+    pub predicate: String,
+    /// Whether the predicate must hold or must not hold
+    pub holds: bool,
+}
+
+impl Condition {
+    fn matches(&self, predicates: &BTreeSet<String>) -> bool {
+        predicates.contains(&self.predicate) == self.holds
+    }
+}
+
+/// If every condition matches, the transaction was made by `wallet`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct Rule {
+    pub conditions: Vec<Condition>,
+    pub wallet: WalletType,
+    /// Number of training samples that reached this rule
+    pub support: u64,
+    /// Fraction of those samples labeled `wallet`
+    pub confidence: f32,
+}
+
+/// A set of mutually exclusive rules learned from labeled transactions
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "ffi", derive(uniffi::Record))]
+pub struct Ruleset {
+    pub rules: Vec<Rule>,
+}
+
+impl Ruleset {
+    /// Returns the wallet of the first rule matching the transaction
+    pub fn classify(&self, heuristics: &Heuristics) -> Option<WalletType> {
+        let predicates = Traits::new(heuristics).predicates();
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.conditions
+                    .iter()
+                    .all(|condition| condition.matches(&predicates))
+            })
+            .map(|rule| rule.wallet)
+    }
+}
+
+/// A version header, then one rule per line:
+/// `version=2 & !sequence~final => BitcoinCore # support=12 confidence=0.9166667`
+impl fmt::Display for Ruleset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# wallet-fingerprint ruleset v{}", RULESET_VERSION)?;
+        for rule in self.rules.iter() {
+            let conditions = if rule.conditions.is_empty() {
+                "*".to_string()
+            } else {
+                rule.conditions
+                    .iter()
+                    .map(|condition| {
+                        let negation = if condition.holds { "" } else { "!" };
+                        format!("{}{}", negation, condition.predicate)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" & ")
+            };
+            writeln!(
+                f,
+                "{} => {} # support={} confidence={}",
+                conditions, rule.wallet, rule.support, rule.confidence
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Ruleset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().map(str::trim).filter(|line| !line.is_empty());
+        let header = lines.next().unwrap_or_default();
+        let version = header
+            .strip_prefix("# wallet-fingerprint ruleset v")
+            .ok_or_else(|| format!("missing ruleset version header: {}", header))?;
+        if version != RULESET_VERSION.to_string() {
+            return Err(format!("unsupported ruleset version: {}", version));
+        }
+
+        let mut rules = Vec::new();
+        for line in lines {
+            if line.starts_with('#') {
+                continue;
+            }
+            let (conditions, rest) = line
+                .split_once(" => ")
+                .ok_or_else(|| format!("missing `=>` in rule: {}", line))?;
+            let (wallet, metadata) = rest.split_once(" # ").unwrap_or((rest, ""));
+
+            let conditions = if conditions == "*" {
+                vec![]
+            } else {
+                conditions
+                    .split(" & ")
+                    .map(|condition| match condition.strip_prefix('!') {
+                        Some(predicate) => Condition {
+                            predicate: predicate.to_string(),
+                            holds: false,
+                        },
+                        None => Condition {
+                            predicate: condition.to_string(),
+                            holds: true,
+                        },
+                    })
+                    .collect()
+            };
+
+            let mut support = 0;
+            let mut confidence = 1.0;
+            for field in metadata.split_whitespace() {
+                match field.split_once('=') {
+                    Some(("support", value)) => {
+                        support = value
+                            .parse()
+                            .map_err(|_| format!("invalid support: {}", value))?
+                    }
+                    Some(("confidence", value)) => {
+                        confidence = value
+                            .parse()
+                            .map_err(|_| format!("invalid confidence: {}", value))?
+                    }
+                    _ => return Err(format!("unknown rule metadata: {}", field)),
+                }
+            }
+
+            rules.push(Rule {
+                conditions,
+                wallet: wallet.trim().parse()?,
+                support,
+                confidence,
+            });
+        }
+
+        Ok(Ruleset { rules })
+    }
+}
+
+/// Gini impurity of the wallet labels
+fn gini(samples: &[(&BTreeSet<String>, WalletType)]) -> f32 {
+    let mut counts: HashMap<WalletType, usize> = HashMap::new();
+    for (_, wallet) in samples {
+        *counts.entry(*wallet).or_default() += 1;
+    }
+    let total = samples.len() as f32;
+    1.0 - counts
+        .values()
+        .map(|count| (*count as f32 / total).powi(2))
+        .sum::<f32>()
+}
+
+fn build_tree(
+    samples: &[(&BTreeSet<String>, WalletType)],
+    conditions: Vec<Condition>,
+    config: &TrainingConfig,
+    rules: &mut Vec<Rule>,
+) {
+    let mut counts: HashMap<WalletType, u64> = HashMap::new();
+    for (_, wallet) in samples {
+        *counts.entry(*wallet).or_default() += 1;
+    }
+    // Ties broken by wallet order so training is deterministic
+    let (wallet, count) = counts
+        .iter()
+        .max_by(|(wallet_a, a), (wallet_b, b)| a.cmp(b).then_with(|| wallet_b.cmp(wallet_a)))
+        .map(|(wallet, count)| (*wallet, *count))
+        .expect("Nodes always have samples");
+    let leaf = Rule {
+        conditions: conditions.clone(),
+        wallet,
+        support: samples.len() as u64,
+        confidence: count as f32 / samples.len() as f32,
+    };
+
+    let impurity = gini(samples);
+    if impurity == 0.0
+        || conditions.len() as u32 >= config.max_depth
+        || (samples.len() as u64) < config.min_samples_split
+    {
+        rules.push(leaf);
+        return;
+    }
+
+    // Pick the predicate whose split most reduces impurity.
+    // Candidates are iterated in sorted order, so ties go to the first predicate.
+    let candidates = samples
+        .iter()
+        .flat_map(|(predicates, _)| predicates.iter())
+        .collect::<BTreeSet<_>>();
+    let mut best: Option<(&String, f32)> = None;
+    for predicate in candidates {
+        let (holds, not_holds): (Vec<_>, Vec<_>) = samples
+            .iter()
+            .partition(|(predicates, _)| predicates.contains(predicate));
+        if holds.is_empty() || not_holds.is_empty() {
+            continue;
+        }
+        let total = samples.len() as f32;
+        let split_impurity = holds.len() as f32 / total * gini(&holds)
+            + not_holds.len() as f32 / total * gini(&not_holds);
+        let gain = impurity - split_impurity;
+        if gain > 0.0 && best.is_none_or(|(_, best_gain)| gain > best_gain) {
+            best = Some((predicate, gain));
+        }
+    }
+
+    let Some((predicate, _)) = best else {
+        rules.push(leaf);
+        return;
+    };
+    for holds in [true, false] {
+        let branch = samples
+            .iter()
+            .filter(|(predicates, _)| predicates.contains(predicate) == holds)
+            .copied()
+            .collect::<Vec<_>>();
+        let mut branch_conditions = conditions.clone();
+        branch_conditions.push(Condition {
+            predicate: predicate.clone(),
+            holds,
+        });
+        build_tree(&branch, branch_conditions, config, rules);
+    }
+}
+
+/// Learns a ruleset from the heuristics of labeled transactions
+pub fn learn_ruleset_from_heuristics(
+    samples: &[(Heuristics, WalletType)],
+    config: &TrainingConfig,
+) -> Ruleset {
+    let predicates = samples
+        .iter()
+        .map(|(heuristics, _)| Traits::new(heuristics).predicates())
+        .collect::<Vec<_>>();
+    let samples = predicates
+        .iter()
+        .zip(samples.iter())
+        .map(|(predicates, (_, wallet))| (predicates, *wallet))
+        .collect::<Vec<_>>();
+
+    let mut rules = Vec::new();
+    if !samples.is_empty() {
+        build_tree(&samples, vec![], config, &mut rules);
+    }
+    Ruleset { rules }
+}
+
+/// Computes the heuristics of each labeled transaction and learns a ruleset from them.
+/// Fails if a transaction spends an output missing from its previous transactions.
+pub fn learn_ruleset(
+    samples: &[LabeledTransaction],
+    config: &TrainingConfig,
+) -> Result<Ruleset, String> {
+    let samples = samples
+        .iter()
+        .map(|sample| {
+            let prev_txouts = get_prevouts(&sample.tx, &sample.prev_txs)?;
+            let heuristics =
+                Heuristics::from_prevouts(&sample.tx, &prev_txouts, &ChangeHints::default());
+            Ok((heuristics, sample.wallet))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(learn_ruleset_from_heuristics(&samples, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::p2sh_payment;
+    use bitcoin::{absolute::LockTime, transaction::Version, Sequence};
+
+    fn labeled(
+        seed: u64,
+        version: Version,
+        sequence: Sequence,
+        lock_time: LockTime,
+        wallet: WalletType,
+    ) -> LabeledTransaction {
+        let (tx, prev_txs) = p2sh_payment(seed)
+            .version(version)
+            .sequence(sequence)
+            .lock_time(lock_time)
+            .build();
+        LabeledTransaction {
+            tx,
+            prev_txs,
+            wallet,
+        }
+    }
+
+    fn training_set() -> Vec<LabeledTransaction> {
+        let mut samples = Vec::new();
+        for seed in 0..5 {
+            samples.push(labeled(
+                seed,
+                Version::TWO,
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+                LockTime::from_height(850_000).unwrap(),
+                WalletType::BitcoinCore,
+            ));
+            samples.push(labeled(
+                seed + 10,
+                Version::TWO,
+                Sequence::MAX,
+                LockTime::ZERO,
+                WalletType::Exodus,
+            ));
+            samples.push(labeled(
+                seed + 20,
+                Version::ONE,
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+                LockTime::ZERO,
+                WalletType::Trezor,
+            ));
+        }
+        samples
+    }
+
+    #[test]
+    fn test_learned_rules_classify_training_set() {
+        let samples = training_set();
+        let ruleset = learn_ruleset(&samples, &TrainingConfig::default()).unwrap();
+        assert_eq!(ruleset.rules.len(), 3);
+        assert!(ruleset.rules.iter().all(|rule| rule.confidence == 1.0));
+
+        for sample in samples {
+            let heuristics = Heuristics::from_prevouts(
+                &sample.tx,
+                &get_prevouts(&sample.tx, &sample.prev_txs).unwrap(),
+                &ChangeHints::default(),
+            );
+            assert_eq!(ruleset.classify(&heuristics), Some(sample.wallet));
+        }
+    }
+
+    #[test]
+    fn test_ruleset_round_trip() {
+        let ruleset = learn_ruleset(&training_set(), &TrainingConfig::default()).unwrap();
+        let exported = ruleset.to_string();
+        assert!(exported.starts_with("# wallet-fingerprint ruleset v1\n"));
+
+        let parsed = exported.parse::<Ruleset>().unwrap();
+        assert_eq!(parsed, ruleset);
+        assert_eq!(parsed.to_string(), exported);
+    }
+
+    #[test]
+    fn test_round_trip_keeps_confidence() {
+        let ruleset = Ruleset {
+            rules: vec![Rule {
+                conditions: vec![Condition {
+                    predicate: "version=2".to_string(),
+                    holds: true,
+                }],
+                wallet: WalletType::BitcoinCore,
+                support: 12,
+                confidence: 11.0 / 12.0,
+            }],
+        };
+        let parsed = ruleset.to_string().parse::<Ruleset>().unwrap();
+        assert_eq!(parsed, ruleset);
+    }
+
+    #[test]
+    fn test_parse_invalid_ruleset() {
+        let header = "# wallet-fingerprint ruleset v1\n";
+        assert!(format!("{}version=2 BitcoinCore", header)
+            .parse::<Ruleset>()
+            .is_err());
+        assert!(format!("{}version=2 => NotAWallet", header)
+            .parse::<Ruleset>()
+            .is_err());
+        assert_eq!(
+            "version=2 => BitcoinCore".parse::<Ruleset>().unwrap_err(),
+            "missing ruleset version header: version=2 => BitcoinCore"
+        );
+        assert_eq!(
+            "# wallet-fingerprint ruleset v2\nversion=2 => BitcoinCore"
+                .parse::<Ruleset>()
+                .unwrap_err(),
+            "unsupported ruleset version: 2"
+        );
+    }
+
+    #[test]
+    fn test_learn_ruleset_missing_parents() {
+        let mut samples = training_set();
+        samples[0].prev_txs.clear();
+        let err = learn_ruleset(&samples, &TrainingConfig::default()).unwrap_err();
+        assert!(err.contains("is missing"), "{}", err);
+    }
+}
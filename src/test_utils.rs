@@ -0,0 +1,765 @@
+//! Building synthetic, signed transactions for testing heuristics.
+//! Coins are funded by made up previous transactions, so the result is only meaningful on regtest.
+
+use bitcoin::{
+    absolute::LockTime,
+    blockdata::{opcodes::all::OP_CHECKSIG, script::Builder},
+    ecdsa,
+    hashes::{sha256, Hash},
+    key::{Keypair, TapTweak},
+    script::PushBytes,
+    secp256k1::{All, Message, Secp256k1, SecretKey},
+    sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType},
+    taproot,
+    transaction::Version,
+    Amount, OutPoint, PublicKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+/// The kind of script an input spends or an output pays to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptType {
+    P2pk,
+    P2pkh,
+    /// P2wpkh nested in P2sh
+    P2shP2wpkh,
+    P2wpkh,
+    /// A single key `<pubkey> OP_CHECKSIG` witness script
+    P2wsh,
+    /// Key path spend
+    P2tr,
+}
+
+/// Where the R value of ECDSA signatures falls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureGrinding {
+    /// Ground until R fits in 32 bytes, as Bitcoin Core does
+    LowR,
+    /// R always needs 33 bytes
+    HighR,
+}
+
+/// How inputs or outputs are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxOrdering {
+    /// In the order they were added
+    Unsorted,
+    /// Sorted according to BIP 69
+    Bip69,
+}
+
+/// Where the change output is placed when outputs are unsorted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangePosition {
+    First,
+    Last,
+    /// Clamped to the number of outputs
+    Index(usize),
+}
+
+#[derive(Debug, Clone)]
+struct InputSpec {
+    value: Amount,
+    script_type: ScriptType,
+    sequence: Option<Sequence>,
+}
+
+#[derive(Debug, Clone)]
+struct OutputSpec {
+    value: Amount,
+    script_type: ScriptType,
+}
+
+/// Builds a transaction together with the previous transactions it spends from.
+/// Every input spends output 0 of its own previous transaction.
+///
+/// Keys are derived from `seed`, so two builders with the same settings and seed
+/// produce identical transactions and different seeds produce unrelated ones.
+#[derive(Debug, Clone)]
+pub struct TxBuilder {
+    seed: u64,
+    version: Version,
+    lock_time: LockTime,
+    sequence: Sequence,
+    inputs: Vec<InputSpec>,
+    outputs: Vec<OutputSpec>,
+    change: Option<OutputSpec>,
+    change_position: ChangePosition,
+    input_ordering: TxOrdering,
+    output_ordering: TxOrdering,
+    grinding: SignatureGrinding,
+    compressed_keys: bool,
+    change_reuses_input_address: bool,
+}
+
+impl Default for TxBuilder {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            sequence: Sequence::MAX,
+            inputs: vec![],
+            outputs: vec![],
+            change: None,
+            change_position: ChangePosition::Last,
+            input_ordering: TxOrdering::Unsorted,
+            output_ordering: TxOrdering::Unsorted,
+            grinding: SignatureGrinding::LowR,
+            compressed_keys: true,
+            change_reuses_input_address: false,
+        }
+    }
+}
+
+impl TxBuilder {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn lock_time(mut self, lock_time: LockTime) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+
+    /// The sequence of every input added without its own sequence
+    pub fn sequence(mut self, sequence: Sequence) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    pub fn input(mut self, value: Amount, script_type: ScriptType) -> Self {
+        self.inputs.push(InputSpec {
+            value,
+            script_type,
+            sequence: None,
+        });
+        self
+    }
+
+    pub fn input_with_sequence(
+        mut self,
+        value: Amount,
+        script_type: ScriptType,
+        sequence: Sequence,
+    ) -> Self {
+        self.inputs.push(InputSpec {
+            value,
+            script_type,
+            sequence: Some(sequence),
+        });
+        self
+    }
+
+    /// Adds a payment output
+    pub fn output(mut self, value: Amount, script_type: ScriptType) -> Self {
+        self.outputs.push(OutputSpec { value, script_type });
+        self
+    }
+
+    pub fn change(mut self, value: Amount, script_type: ScriptType) -> Self {
+        self.change = Some(OutputSpec { value, script_type });
+        self
+    }
+
+    /// Sends change back to the script of the first input instead of a fresh one
+    pub fn change_reuses_input_address(mut self, reuse: bool) -> Self {
+        self.change_reuses_input_address = reuse;
+        self
+    }
+
+    /// Ignored when outputs are sorted
+    pub fn change_position(mut self, position: ChangePosition) -> Self {
+        self.change_position = position;
+        self
+    }
+
+    pub fn input_ordering(mut self, ordering: TxOrdering) -> Self {
+        self.input_ordering = ordering;
+        self
+    }
+
+    pub fn output_ordering(mut self, ordering: TxOrdering) -> Self {
+        self.output_ordering = ordering;
+        self
+    }
+
+    pub fn signatures(mut self, grinding: SignatureGrinding) -> Self {
+        self.grinding = grinding;
+        self
+    }
+
+    /// Only affects P2pk and P2pkh scripts, segwit requires compressed keys
+    pub fn compressed_keys(mut self, compressed: bool) -> Self {
+        self.compressed_keys = compressed;
+        self
+    }
+
+    /// Returns the signed transaction and the previous transactions it spends from
+    pub fn build(self) -> (Transaction, Vec<Transaction>) {
+        let secp = Secp256k1::new();
+
+        let mut prev_txs = Vec::new();
+        let mut inputs = Vec::new();
+        for (i, spec) in self.inputs.iter().enumerate() {
+            let key = self.secret_key(b"input", i);
+            let prev_tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(
+                        Txid::from_byte_array(self.tagged_hash(b"funding", i)),
+                        0,
+                    ),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::new(),
+                }],
+                output: vec![TxOut {
+                    value: spec.value,
+                    script_pubkey: self.script_pubkey(&secp, &key, spec.script_type),
+                }],
+            };
+            let txin = TxIn {
+                previous_output: OutPoint::new(prev_tx.compute_txid(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: spec.sequence.unwrap_or(self.sequence),
+                witness: Witness::new(),
+            };
+            inputs.push((txin, key, spec.script_type, prev_tx.output[0].clone()));
+            prev_txs.push(prev_tx);
+        }
+        if self.input_ordering == TxOrdering::Bip69 {
+            inputs.sort_by(|(a, ..), (b, ..)| {
+                a.previous_output
+                    .txid
+                    .to_string()
+                    .cmp(&b.previous_output.txid.to_string())
+                    .then_with(|| a.previous_output.vout.cmp(&b.previous_output.vout))
+            });
+        }
+
+        let mut outputs = self
+            .outputs
+            .iter()
+            .enumerate()
+            .map(|(i, spec)| TxOut {
+                value: spec.value,
+                script_pubkey: self.script_pubkey(
+                    &secp,
+                    &self.secret_key(b"output", i),
+                    spec.script_type,
+                ),
+            })
+            .collect::<Vec<_>>();
+        if let Some(spec) = &self.change {
+            let script_pubkey = match prev_txs.first() {
+                Some(prev_tx) if self.change_reuses_input_address => {
+                    prev_tx.output[0].script_pubkey.clone()
+                }
+                _ => self.script_pubkey(&secp, &self.secret_key(b"change", 0), spec.script_type),
+            };
+            let change = TxOut {
+                value: spec.value,
+                script_pubkey,
+            };
+            let index = match self.change_position {
+                ChangePosition::First => 0,
+                ChangePosition::Last => outputs.len(),
+                ChangePosition::Index(index) => index.min(outputs.len()),
+            };
+            outputs.insert(index, change);
+        }
+        if self.output_ordering == TxOrdering::Bip69 {
+            outputs.sort_by(|a, b| {
+                a.value
+                    .cmp(&b.value)
+                    .then_with(|| a.script_pubkey.cmp(&b.script_pubkey))
+            });
+        }
+
+        let mut tx = Transaction {
+            version: self.version,
+            lock_time: self.lock_time,
+            input: inputs.iter().map(|(txin, ..)| txin.clone()).collect(),
+            output: outputs,
+        };
+
+        // Signatures commit to the final transaction, so sign once everything is in place
+        let prevouts = inputs
+            .iter()
+            .map(|(.., prevout)| prevout.clone())
+            .collect::<Vec<_>>();
+        let spends = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, (_, key, script_type, _))| {
+                self.spend(&secp, &tx, &prevouts, i, key, *script_type)
+            })
+            .collect::<Vec<_>>();
+        for (txin, (script_sig, witness)) in tx.input.iter_mut().zip(spends) {
+            txin.script_sig = script_sig;
+            txin.witness = witness;
+        }
+
+        (tx, prev_txs)
+    }
+
+    /// Returns the signed transaction and the coins it spends, for testing heuristics directly
+    #[cfg(test)]
+    pub(crate) fn build_with_prevouts(self) -> (Transaction, Vec<crate::util::TxOutWithOutpoint>) {
+        let (tx, prev_txs) = self.build();
+        let prev_outs = crate::util::get_prevouts(&tx, &prev_txs).expect("Every coin is funded");
+        (tx, prev_outs)
+    }
+
+    fn tagged_hash(&self, tag: &[u8], index: usize) -> [u8; 32] {
+        let mut data = self.seed.to_le_bytes().to_vec();
+        data.extend_from_slice(tag);
+        data.extend_from_slice(&(index as u64).to_le_bytes());
+        sha256::Hash::hash(&data).to_byte_array()
+    }
+
+    fn secret_key(&self, tag: &[u8], index: usize) -> SecretKey {
+        SecretKey::from_slice(&self.tagged_hash(tag, index)).expect("Hash is a valid key")
+    }
+
+    fn public_key(&self, secp: &Secp256k1<All>, key: &SecretKey, compressed: bool) -> PublicKey {
+        let mut pubkey = PublicKey::new(key.public_key(secp));
+        pubkey.compressed = compressed;
+        pubkey
+    }
+
+    fn script_pubkey(
+        &self,
+        secp: &Secp256k1<All>,
+        key: &SecretKey,
+        script_type: ScriptType,
+    ) -> ScriptBuf {
+        let pubkey = self.public_key(secp, key, true);
+        match script_type {
+            ScriptType::P2pk => {
+                ScriptBuf::new_p2pk(&self.public_key(secp, key, self.compressed_keys))
+            }
+            ScriptType::P2pkh => ScriptBuf::new_p2pkh(
+                &self
+                    .public_key(secp, key, self.compressed_keys)
+                    .pubkey_hash(),
+            ),
+            ScriptType::P2shP2wpkh => ScriptBuf::new_p2sh(&p2wpkh_script(&pubkey).script_hash()),
+            ScriptType::P2wpkh => p2wpkh_script(&pubkey),
+            ScriptType::P2wsh => {
+                ScriptBuf::new_p2wsh(&p2wsh_witness_script(&pubkey).wscript_hash())
+            }
+            ScriptType::P2tr => {
+                let (xonly, _) = pubkey.inner.x_only_public_key();
+                ScriptBuf::new_p2tr(secp, xonly, None)
+            }
+        }
+    }
+
+    /// Returns the script sig and witness spending input `index` of `tx` with `key`
+    fn spend(
+        &self,
+        secp: &Secp256k1<All>,
+        tx: &Transaction,
+        prevouts: &[TxOut],
+        index: usize,
+        key: &SecretKey,
+        script_type: ScriptType,
+    ) -> (ScriptBuf, Witness) {
+        let mut cache = SighashCache::new(tx);
+        let pubkey = self.public_key(secp, key, true);
+        let prevout = &prevouts[index];
+        let script_code = match script_type {
+            ScriptType::P2shP2wpkh | ScriptType::P2wpkh => p2wpkh_script(&pubkey),
+            ScriptType::P2wsh => p2wsh_witness_script(&pubkey),
+            _ => prevout.script_pubkey.clone(),
+        };
+        let sighash = match script_type {
+            ScriptType::P2pk | ScriptType::P2pkh => cache
+                .legacy_signature_hash(index, &script_code, EcdsaSighashType::All.to_u32())
+                .expect("Input index is in range")
+                .to_byte_array(),
+            ScriptType::P2shP2wpkh | ScriptType::P2wpkh => cache
+                .p2wpkh_signature_hash(index, &script_code, prevout.value, EcdsaSighashType::All)
+                .expect("Script code is p2wpkh")
+                .to_byte_array(),
+            ScriptType::P2wsh => cache
+                .p2wsh_signature_hash(index, &script_code, prevout.value, EcdsaSighashType::All)
+                .expect("Input index is in range")
+                .to_byte_array(),
+            ScriptType::P2tr => cache
+                .taproot_key_spend_signature_hash(
+                    index,
+                    &Prevouts::All(prevouts),
+                    TapSighashType::Default,
+                )
+                .expect("Prevouts are complete")
+                .to_byte_array(),
+        };
+        let message = Message::from_digest(sighash);
+        let ecdsa_sig = || ecdsa::Signature {
+            signature: match self.grinding {
+                SignatureGrinding::LowR => secp.sign_ecdsa_low_r(&message, key),
+                SignatureGrinding::HighR => (0u64..)
+                    .map(|counter| {
+                        let mut noncedata = [0u8; 32];
+                        noncedata[..8].copy_from_slice(&counter.to_le_bytes());
+                        secp.sign_ecdsa_with_noncedata(&message, key, &noncedata)
+                    })
+                    .find(|sig| sig.serialize_compact()[0] >= 0x80)
+                    .expect("Half of all nonces give a high R"),
+            },
+            sighash_type: EcdsaSighashType::All,
+        };
+
+        match script_type {
+            ScriptType::P2pk => (
+                Builder::new()
+                    .push_slice(ecdsa_sig().serialize())
+                    .into_script(),
+                Witness::new(),
+            ),
+            ScriptType::P2pkh => (
+                Builder::new()
+                    .push_slice(ecdsa_sig().serialize())
+                    .push_key(&self.public_key(secp, key, self.compressed_keys))
+                    .into_script(),
+                Witness::new(),
+            ),
+            ScriptType::P2shP2wpkh => {
+                let redeem_script: &PushBytes = script_code
+                    .as_bytes()
+                    .try_into()
+                    .expect("Redeem script is small");
+                (
+                    Builder::new().push_slice(redeem_script).into_script(),
+                    Witness::p2wpkh(&ecdsa_sig(), &pubkey.inner),
+                )
+            }
+            ScriptType::P2wpkh => (
+                ScriptBuf::new(),
+                Witness::p2wpkh(&ecdsa_sig(), &pubkey.inner),
+            ),
+            ScriptType::P2wsh => {
+                let mut witness = Witness::new();
+                witness.push(ecdsa_sig().serialize());
+                witness.push(script_code);
+                (ScriptBuf::new(), witness)
+            }
+            ScriptType::P2tr => {
+                let keypair = Keypair::from_secret_key(secp, key).tap_tweak(secp, None);
+                let signature = taproot::Signature {
+                    signature: secp.sign_schnorr_no_aux_rand(&message, &keypair.to_keypair()),
+                    sighash_type: TapSighashType::Default,
+                };
+                (ScriptBuf::new(), Witness::p2tr_key_spend(&signature))
+            }
+        }
+    }
+}
+
+fn p2wpkh_script(pubkey: &PublicKey) -> ScriptBuf {
+    ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash().expect("Key is compressed"))
+}
+
+fn p2wsh_witness_script(pubkey: &PublicKey) -> ScriptBuf {
+    Builder::new()
+        .push_key(pubkey)
+        .push_opcode(OP_CHECKSIG)
+        .into_script()
+}
+
+/// Makes an arbitrary transaction spend `prevouts`, e.g. for fuzzing.
+/// Input `i` is rewired to a made up previous transaction paying `prevouts[i]`,
+/// cycling through `prevouts` if there are fewer of them than inputs.
+pub fn spend_prevouts(mut tx: Transaction, prevouts: &[TxOut]) -> (Transaction, Vec<Transaction>) {
+    let mut prev_txs = Vec::new();
+    for (i, txin) in tx.input.iter_mut().enumerate() {
+        let prevout = match prevouts.len() {
+            0 => TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::new(),
+            },
+            n => prevouts[i % n].clone(),
+        };
+        let prev_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), i as u32),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![prevout],
+        };
+        txin.previous_output = OutPoint::new(prev_tx.compute_txid(), 0);
+        prev_txs.push(prev_tx);
+    }
+    (tx, prev_txs)
+}
+
+/// A payment from a p2wpkh coin to a p2sh output with p2wpkh change,
+/// for tests that only vary the remaining settings
+#[cfg(test)]
+pub(crate) fn p2sh_payment(seed: u64) -> TxBuilder {
+    TxBuilder::new(seed)
+        .input(Amount::from_sat(50_000), ScriptType::P2wpkh)
+        .output(Amount::from_sat(20_000), ScriptType::P2shP2wpkh)
+        .change(Amount::from_sat(29_000 + seed), ScriptType::P2wpkh)
+}
+
+/// A payment from p2wpkh coins of the given values to outputs of the given values and types
+#[cfg(test)]
+pub(crate) fn payment(inputs: &[u64], outputs: &[(u64, ScriptType)]) -> TxBuilder {
+    let builder = inputs.iter().fold(TxBuilder::new(0), |builder, value| {
+        builder.input(Amount::from_sat(*value), ScriptType::P2wpkh)
+    });
+    outputs
+        .iter()
+        .fold(builder, |builder, (value, script_type)| {
+            builder.output(Amount::from_sat(*value), *script_type)
+        })
+}
+
+/// Proptest strategies for transactions that are structurally valid but otherwise arbitrary
+#[cfg(test)]
+pub(crate) mod strategy {
+    use bitcoin::{
+        absolute::LockTime, hashes::Hash, secp256k1::ecdsa::Signature, transaction::Version,
+        Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, WPubkeyHash,
+        Witness,
+    };
+    use proptest::{collection::vec, prelude::*};
+
+    /// Arbitrary bytes, or bytes shaped like a DER or schnorr signature
+    fn push() -> impl Strategy<Value = Vec<u8>> {
+        prop_oneof![
+            vec(any::<u8>(), 0..80),
+            (any::<[u8; 32]>(), any::<[u8; 32]>(), any::<u8>()).prop_filter_map(
+                "overflowing signature",
+                |(r, s, sighash)| {
+                    let mut compact = [0u8; 64];
+                    compact[..32].copy_from_slice(&r);
+                    compact[32..].copy_from_slice(&s);
+                    let mut sig = Signature::from_compact(&compact)
+                        .ok()?
+                        .serialize_der()
+                        .to_vec();
+                    sig.push(sighash);
+                    Some(sig)
+                }
+            ),
+            vec(any::<u8>(), 64..=65),
+        ]
+    }
+
+    /// Arbitrary bytes, or a standard script
+    fn script() -> impl Strategy<Value = ScriptBuf> {
+        prop_oneof![
+            vec(any::<u8>(), 0..40).prop_map(ScriptBuf::from_bytes),
+            any::<[u8; 20]>()
+                .prop_map(|hash| ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array(hash))),
+            any::<[u8; 32]>().prop_map(|key| {
+                let mut bytes = vec![0x51, 0x20];
+                bytes.extend_from_slice(&key);
+                ScriptBuf::from_bytes(bytes)
+            }),
+            Just(ScriptBuf::new_op_return([0u8; 4])),
+        ]
+    }
+
+    /// Any amount, consensus decoding also accepts values above `MAX_MONEY`
+    pub(crate) fn txout() -> impl Strategy<Value = TxOut> {
+        (any::<u64>(), script()).prop_map(|(value, script_pubkey)| TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey,
+        })
+    }
+
+    fn txin() -> impl Strategy<Value = TxIn> {
+        (
+            any::<[u8; 32]>(),
+            any::<u32>(),
+            vec(push(), 0..3),
+            vec(push(), 0..3),
+            any::<u32>(),
+        )
+            .prop_map(|(txid, vout, pushes, witness, sequence)| {
+                let mut script_sig = ScriptBuf::builder();
+                for push in pushes {
+                    let push: &bitcoin::script::PushBytes =
+                        push.as_slice().try_into().expect("Pushes are small");
+                    script_sig = script_sig.push_slice(push);
+                }
+                TxIn {
+                    previous_output: OutPoint::new(Txid::from_byte_array(txid), vout),
+                    script_sig: script_sig.into_script(),
+                    sequence: Sequence(sequence),
+                    witness: Witness::from_slice(&witness),
+                }
+            })
+    }
+
+    pub(crate) fn transaction() -> impl Strategy<Value = Transaction> {
+        (
+            any::<i32>(),
+            any::<u32>(),
+            vec(txin(), 0..5),
+            vec(txout(), 0..5),
+        )
+            .prop_map(|(version, lock_time, input, output)| Transaction {
+                version: Version(version),
+                lock_time: LockTime::from_consensus(lock_time),
+                input,
+                output,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input::{get_input_order, low_order_r_grinding, InputSortingType},
+        output::{get_output_structure, OutputStructureType},
+        util::{get_prevouts, OutputType},
+    };
+    use bitcoin::AddressType;
+
+    #[test]
+    fn test_script_types() {
+        let builder = TxBuilder::new(1)
+            .input(Amount::from_sat(10_000), ScriptType::P2pkh)
+            .input(Amount::from_sat(20_000), ScriptType::P2shP2wpkh)
+            .input(Amount::from_sat(30_000), ScriptType::P2wpkh)
+            .input(Amount::from_sat(40_000), ScriptType::P2wsh)
+            .input(Amount::from_sat(50_000), ScriptType::P2tr)
+            .output(Amount::from_sat(100_000), ScriptType::P2tr);
+        let (tx, prev_txs) = builder.clone().build();
+
+        let input_types = get_prevouts(&tx, &prev_txs)
+            .unwrap()
+            .iter()
+            .map(|prev_out| prev_out.get_type())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            input_types,
+            [
+                AddressType::P2pkh,
+                AddressType::P2sh,
+                AddressType::P2wpkh,
+                AddressType::P2wsh,
+                AddressType::P2tr
+            ]
+            .map(OutputType::Address)
+        );
+        // Sized like real signatures
+        assert_eq!(tx.input[2].witness.nth(0).unwrap().len(), 71);
+        assert_eq!(tx.input[4].witness.nth(0).unwrap().len(), 64);
+        // Deterministic given the seed
+        assert_eq!(builder.build().0, tx);
+    }
+
+    #[test]
+    fn test_signatures_commit_to_transaction() {
+        let (tx, prev_txs) = TxBuilder::new(4)
+            .input(Amount::from_sat(10_000), ScriptType::P2wpkh)
+            .input(Amount::from_sat(20_000), ScriptType::P2tr)
+            .output(Amount::from_sat(29_000), ScriptType::P2wpkh)
+            .build();
+        let prevouts = get_prevouts(&tx, &prev_txs)
+            .unwrap()
+            .into_iter()
+            .map(|prev_out| prev_out.txout)
+            .collect::<Vec<_>>();
+        let secp = Secp256k1::verification_only();
+        let mut cache = SighashCache::new(&tx);
+
+        let sig = ecdsa::Signature::from_slice(tx.input[0].witness.nth(0).unwrap()).unwrap();
+        let pubkey = PublicKey::from_slice(tx.input[0].witness.nth(1).unwrap()).unwrap();
+        let sighash = cache
+            .p2wpkh_signature_hash(
+                0,
+                &prevouts[0].script_pubkey,
+                prevouts[0].value,
+                sig.sighash_type,
+            )
+            .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        assert!(secp
+            .verify_ecdsa(&message, &sig.signature, &pubkey.inner)
+            .is_ok());
+
+        let sig = taproot::Signature::from_slice(tx.input[1].witness.nth(0).unwrap()).unwrap();
+        let sighash = cache
+            .taproot_key_spend_signature_hash(1, &Prevouts::All(&prevouts), sig.sighash_type)
+            .unwrap();
+        let output_key =
+            bitcoin::key::XOnlyPublicKey::from_slice(&prevouts[1].script_pubkey.as_bytes()[2..])
+                .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        assert!(secp
+            .verify_schnorr(&sig.signature, &message, &output_key)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_signature_grinding() {
+        let build = |grinding| {
+            TxBuilder::new(2)
+                .input(Amount::from_sat(10_000), ScriptType::P2wpkh)
+                .output(Amount::from_sat(9_000), ScriptType::P2wpkh)
+                .signatures(grinding)
+                .build()
+                .0
+        };
+        let low_r = build(SignatureGrinding::LowR);
+        let high_r = build(SignatureGrinding::HighR);
+        assert_eq!(low_r.input[0].witness.nth(0).unwrap().len(), 71);
+        assert_eq!(high_r.input[0].witness.nth(0).unwrap().len(), 72);
+        assert!(low_order_r_grinding(&low_r));
+        assert!(!low_order_r_grinding(&high_r));
+    }
+
+    #[test]
+    fn test_ordering_and_change_position() {
+        let builder = TxBuilder::new(3)
+            .input(Amount::from_sat(30_000), ScriptType::P2wpkh)
+            .input(Amount::from_sat(10_000), ScriptType::P2wpkh)
+            .input(Amount::from_sat(20_000), ScriptType::P2wpkh)
+            .output(Amount::from_sat(40_000), ScriptType::P2wpkh)
+            .change(Amount::from_sat(15_000), ScriptType::P2wpkh)
+            .change_position(ChangePosition::First)
+            .sequence(Sequence::ENABLE_RBF_NO_LOCKTIME);
+
+        let (tx, prev_txs) = builder.clone().build();
+        assert_eq!(tx.output[0].value, Amount::from_sat(15_000));
+        assert!(tx
+            .input
+            .iter()
+            .all(|txin| txin.sequence == Sequence::ENABLE_RBF_NO_LOCKTIME));
+        let prev_outs = get_prevouts(&tx, &prev_txs).unwrap();
+        assert_eq!(
+            get_input_order(&tx, &prev_outs),
+            vec![InputSortingType::Unknown]
+        );
+
+        let (tx, prev_txs) = builder
+            .input_ordering(TxOrdering::Bip69)
+            .output_ordering(TxOrdering::Bip69)
+            .build();
+        let prev_outs = get_prevouts(&tx, &prev_txs).unwrap();
+        assert!(get_input_order(&tx, &prev_outs).contains(&InputSortingType::Bip69));
+        assert!(get_output_structure(&tx, &prev_outs).contains(&OutputStructureType::Bip69));
+    }
+}
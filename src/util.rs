@@ -1,11 +1,12 @@
 use bitcoin::{
     blockdata::script::Instruction, ecdsa::Signature as EcdsaSignature,
-    secp256k1::schnorr::Signature as SchnorrSignature, Address, AddressType, Network, OutPoint,
-    Script, Transaction, TxOut,
+    taproot::Signature as SchnorrSignature, Address, AddressType, Network, OutPoint, Script,
+    Transaction, TxIn, TxOut,
 };
+use std::fmt;
 
 /// Extracts ECDSA signatures from a scriptSig
-fn extract_signatures_from_scriptsig(script_sig: &Script) -> Vec<Vec<u8>> {
+pub(crate) fn extract_signatures_from_scriptsig(script_sig: &Script) -> Vec<Vec<u8>> {
     script_sig
         .instructions()
         .filter_map(|instr| match instr {
@@ -20,7 +21,7 @@ fn extract_signatures_from_scriptsig(script_sig: &Script) -> Vec<Vec<u8>> {
 }
 
 /// Extracts ECDSA signatures from witness stack
-fn extract_signatures_from_witness(witness: &bitcoin::Witness) -> Vec<Vec<u8>> {
+pub(crate) fn extract_signatures_from_witness(witness: &bitcoin::Witness) -> Vec<Vec<u8>> {
     witness
         .iter()
         .filter(|data| {
@@ -30,20 +31,20 @@ fn extract_signatures_from_witness(witness: &bitcoin::Witness) -> Vec<Vec<u8>> {
         .collect()
 }
 
+/// Extract sigs from a single input, picking scriptSig OR witness
+pub(crate) fn extract_signatures(txin: &TxIn) -> Vec<Vec<u8>> {
+    if !txin.script_sig.is_empty() {
+        extract_signatures_from_scriptsig(&txin.script_sig)
+    } else if !txin.witness.is_empty() {
+        extract_signatures_from_witness(&txin.witness)
+    } else {
+        vec![]
+    }
+}
+
 /// Extract all sigs from tx.inputs, picking scriptSig OR witness per input
 pub(crate) fn extract_all_signatures(tx: &Transaction) -> Vec<Vec<u8>> {
-    tx.input
-        .iter()
-        .flat_map(|txin| {
-            if !txin.script_sig.is_empty() {
-                extract_signatures_from_scriptsig(&txin.script_sig)
-            } else if !txin.witness.is_empty() {
-                extract_signatures_from_witness(&txin.witness)
-            } else {
-                vec![]
-            }
-        })
-        .collect()
+    tx.input.iter().flat_map(extract_signatures).collect()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -57,26 +58,70 @@ pub enum OutputType {
     Address(AddressType),
 }
 
-pub(crate) fn get_output_type(prevout: &TxOut) -> OutputType {
-    let address =
-        // FIXME: hardcoded network
-        Address::from_script(&prevout.script_pubkey, Network::Bitcoin).expect("Always valid types");
-    if let Some(address_type) = address.address_type() {
-        return OutputType::Address(address_type);
-    } else {
-        if prevout.script_pubkey.is_op_return() {
-            return OutputType::Opreturn;
-        } else {
-            return OutputType::NonStandard;
+impl fmt::Display for OutputType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputType::Opreturn => write!(f, "opreturn"),
+            OutputType::NonStandard => write!(f, "nonstandard"),
+            OutputType::Address(address_type) => write!(f, "{}", address_type),
         }
     }
 }
 
+pub(crate) fn get_output_type(prevout: &TxOut) -> OutputType {
+    // FIXME: hardcoded network
+    match Address::from_script(&prevout.script_pubkey, Network::Bitcoin)
+        .ok()
+        .and_then(|address| address.address_type())
+    {
+        Some(address_type) => OutputType::Address(address_type),
+        None if prevout.script_pubkey.is_op_return() => OutputType::Opreturn,
+        None => OutputType::NonStandard,
+    }
+}
+
+/// Looks up the output spent by each input of the transaction in the previous transactions.
+/// Fails if a previous transaction is missing or does not have the spent output.
+pub(crate) fn get_prevouts(
+    tx: &Transaction,
+    prev_txs: &[Transaction],
+) -> Result<Vec<TxOutWithOutpoint>, String> {
+    tx.input
+        .iter()
+        .enumerate()
+        .map(|(i, txin)| {
+            let outpoint = txin.previous_output;
+            let prev_tx = prev_txs
+                .iter()
+                .find(|prev_tx| prev_tx.compute_txid() == outpoint.txid)
+                .ok_or_else(|| {
+                    format!(
+                        "input {}: previous transaction {} is missing",
+                        i, outpoint.txid
+                    )
+                })?;
+            let txout = prev_tx.output.get(outpoint.vout as usize).ok_or_else(|| {
+                format!(
+                    "input {}: previous transaction {} has no output {}",
+                    i, outpoint.txid, outpoint.vout
+                )
+            })?;
+            Ok(TxOutWithOutpoint {
+                txout: txout.clone(),
+                outpoint,
+                height: None,
+            })
+        })
+        .collect()
+}
+
 /// TxOut with OutPoint of the tx input spending the output
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct TxOutWithOutpoint {
     pub(crate) txout: TxOut,
     pub(crate) outpoint: OutPoint,
+    /// Height of the block confirming the output, if known
+    pub(crate) height: Option<u32>,
 }
 
 impl TxOutWithOutpoint {